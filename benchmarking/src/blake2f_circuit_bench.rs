@@ -36,6 +36,8 @@ mod tests {
             outputs,
             _marker: PhantomData,
         };
+        let instance = circuit.instance();
+        let instance = instance.iter().map(|column| column.as_slice()).collect::<Vec<_>>();
 
         // Initialize the polynomial commitment parameters.
         let mut rng = XorShiftRng::from_seed([
@@ -72,7 +74,7 @@ mod tests {
             &general_params,
             &pk,
             &[circuit],
-            &[&[]],
+            &[&instance[..]],
             rng,
             &mut transcript,
         )
@@ -94,7 +96,7 @@ mod tests {
             &verifier_params,
             pk.get_vk(),
             strategy,
-            &[&[]],
+            &[&instance[..]],
             &mut verifier_transcript,
         )
         .expect("failed to verify bench circuit");
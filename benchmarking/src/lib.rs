@@ -1,5 +1,8 @@
 #[cfg(test)]
 pub mod blake2f_circuit_bench;
 
+#[cfg(test)]
+pub mod blake2f_rows_bench;
+
 #[cfg(test)]
 mod constants;
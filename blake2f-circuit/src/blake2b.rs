@@ -0,0 +1,952 @@
+//! The BLAKE2b hash function (RFC 7693) over byte inputs, built on the F
+//! compression function of `Blake2fChip`.
+//!
+//! The input is split into 128-byte blocks, the last one padded with zeros.
+//! Every block is compressed in 12 rounds from the state the previous one
+//! ended with, with the number of input bytes up to the end of the block as
+//! its offset counter t, and with the final block flag set on the last one.
+//! An empty input is a single block of zeros.
+//!
+//! A keyed hash, the MAC of the input under a key of 1 to 64 bytes, is the
+//! hash of the key padded with zeros to a whole block followed by the input,
+//! with the key length in the parameter block.
+//!
+//! The parameter block also sets the digest length, the fanout and depth,
+//! and a salt and a personalization, as Zcash and libsodium use to
+//! separate the domains of their hashes. A digest of 1 to 64 bytes is the
+//! output truncated to its length, which the parameter block commits to,
+//! so BLAKE2b-256 is not a prefix of BLAKE2b-512.
+//!
+//! The length of the input can also be witnessed, up to the number of input
+//! bytes laid out. The padding, t and the final block are then all derived
+//! in the circuit from the length, and the digest is selected from the
+//! state after the final block.
+
+use std::ops::Range;
+
+use halo2_proofs::{
+    arithmetic::FieldExt,
+    circuit::{AssignedCell, Layouter, Region, Value},
+    plonk::Error,
+};
+
+use crate::blake2f::{Blake2fChip, Blake2fConfig, Blake2fInstructions, ROUNDS};
+use crate::compression::{compress_native, Block, State, BLAKE2B_IV, STATE};
+
+/// The number of bytes of a message block.
+pub const BLOCK_BYTES: usize = 128;
+
+/// The number of bytes of the longest digest, that of BLAKE2b-512.
+pub const OUTBYTES: usize = 64;
+
+/// A cell holding a byte. The bytes of the input are range-checked by the
+/// hash, which copies them into the byte decomposition of the message words.
+pub type AssignedByte<F> = AssignedCell<F, F>;
+
+/// The number of bytes of the longest key.
+pub const KEYBYTES: usize = 64;
+
+/// The number of bytes of the salt.
+pub const SALTBYTES: usize = 16;
+
+/// The number of bytes of the personalization.
+pub const PERSONALBYTES: usize = 16;
+
+/// The parameter block of a sequential hash, XORed into the IV to give the
+/// initial state h[0..7]. Its first word holds the digest length, the key
+/// length, the fanout and the depth, its last four words the salt and the
+/// personalization, and the leaf length, node offset, node depth and inner
+/// length of tree hashing are left at zero. The default is that of an
+/// unkeyed BLAKE2b-512 hash, without salt or personalization.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ParamBlock {
+    /// The number of bytes of the digest, from 1 to `OUTBYTES`
+    pub digest_length: u8,
+    /// The number of bytes of the key, at most `KEYBYTES`
+    pub key_length: u8,
+    pub fanout: u8,
+    pub depth: u8,
+    pub salt: [u8; SALTBYTES],
+    pub personal: [u8; PERSONALBYTES],
+}
+
+impl Default for ParamBlock {
+    fn default() -> Self {
+        Self {
+            digest_length: OUTBYTES as u8,
+            key_length: 0,
+            fanout: 1,
+            depth: 1,
+            salt: [0; SALTBYTES],
+            personal: [0; PERSONALBYTES],
+        }
+    }
+}
+
+impl ParamBlock {
+    /// The parameter block of an unkeyed hash with a digest of
+    /// `digest_length` bytes, BLAKE2b-256 for 32 of them, or `None` if the
+    /// length is not from 1 to `OUTBYTES`.
+    pub fn with_digest_length(digest_length: usize) -> Option<Self> {
+        let params = Self {
+            digest_length: u8::try_from(digest_length).ok()?,
+            ..Default::default()
+        };
+        if params.is_valid() {
+            Some(params)
+        } else {
+            None
+        }
+    }
+
+    /// The parameter block of a BLAKE2b-512 MAC under a key of `key_length`
+    /// bytes, or `None` if the key is longer than `KEYBYTES`.
+    pub fn keyed(key_length: usize) -> Option<Self> {
+        let params = Self {
+            key_length: u8::try_from(key_length).ok()?,
+            ..Default::default()
+        };
+        if params.is_valid() {
+            Some(params)
+        } else {
+            None
+        }
+    }
+
+    /// Whether the digest and key lengths are in range.
+    pub fn is_valid(&self) -> bool {
+        (1..=OUTBYTES).contains(&(self.digest_length as usize)) && self.key_length as usize <= KEYBYTES
+    }
+
+    /// The 64 bytes of the parameter block.
+    pub fn to_bytes(&self) -> [u8; 64] {
+        let mut bytes = [0; 64];
+        bytes[..4].copy_from_slice(&[self.digest_length, self.key_length, self.fanout, self.depth]);
+        bytes[32..48].copy_from_slice(&self.salt);
+        bytes[48..].copy_from_slice(&self.personal);
+        bytes
+    }
+
+    /// The initial state h[0..7] of a hash with these parameters.
+    pub fn initial_state(&self) -> [u64; STATE] {
+        let mut h = BLAKE2B_IV;
+        for (word, bytes) in h.iter_mut().zip(self.to_bytes().chunks(8)) {
+            *word ^= u64::from_le_bytes(bytes.try_into().expect("8-byte chunk"));
+        }
+        h
+    }
+}
+
+/// Computes the BLAKE2b-512 digest of `input` natively.
+pub fn blake2b(input: &[u8]) -> [u8; OUTBYTES] {
+    hash_native(ParamBlock::default().initial_state(), input)
+}
+
+/// Computes the BLAKE2b-512 MAC of `input` under `key` natively.
+///
+/// # Panics
+///
+/// Panics if the key is empty or longer than `KEYBYTES`.
+pub fn blake2b_mac(key: &[u8], input: &[u8]) -> [u8; OUTBYTES] {
+    assert!(!key.is_empty(), "empty key");
+    let params = ParamBlock::keyed(key.len()).expect("key of at most KEYBYTES bytes");
+    let tag = blake2b_with_params(&params, key, input);
+    tag.try_into().expect("64-byte tag")
+}
+
+/// Computes the digest of `params.digest_length` bytes of `input` with the
+/// parameters `params` natively, under `key` if it is not empty.
+///
+/// # Panics
+///
+/// Panics if the parameters are out of range or if the key is not
+/// `params.key_length` bytes long.
+pub fn blake2b_with_params(params: &ParamBlock, key: &[u8], input: &[u8]) -> Vec<u8> {
+    assert!(params.is_valid(), "invalid parameters {:?}", params);
+    assert_eq!(key.len(), params.key_length as usize, "key of {} bytes", key.len());
+    let output = if key.is_empty() {
+        hash_native(params.initial_state(), input)
+    } else {
+        let mut data = key.to_vec();
+        data.resize(BLOCK_BYTES, 0);
+        data.extend_from_slice(input);
+        hash_native(params.initial_state(), &data)
+    };
+    output[..params.digest_length as usize].to_vec()
+}
+
+// Hashes `input` natively from the initial state `h`
+fn hash_native(mut h: [u64; STATE], input: &[u8]) -> [u8; OUTBYTES] {
+    let num_blocks = num_blocks(input.len());
+    for idx in 0..num_blocks {
+        let mut block = [0; BLOCK_BYTES];
+        let bytes = &input[block_range(idx, input.len())];
+        block[..bytes.len()].copy_from_slice(bytes);
+
+        let mut m = [0; 16];
+        for (word, bytes) in m.iter_mut().zip(block.chunks(8)) {
+            *word = u64::from_le_bytes(bytes.try_into().expect("8-byte chunk"));
+        }
+        h = compress_native(ROUNDS as u32, h, m, counter(idx, input.len()), idx == num_blocks - 1);
+    }
+
+    let mut digest = [0; OUTBYTES];
+    for (bytes, word) in digest.chunks_mut(8).zip(h) {
+        bytes.copy_from_slice(&word.to_le_bytes());
+    }
+    digest
+}
+
+// An empty input still takes a block
+fn num_blocks(len: usize) -> usize {
+    ((len + BLOCK_BYTES - 1) / BLOCK_BYTES).max(1)
+}
+
+// The bytes of an input of `len` bytes in the block at `idx`
+fn block_range(idx: usize, len: usize) -> Range<usize> {
+    (idx * BLOCK_BYTES).min(len)..((idx + 1) * BLOCK_BYTES).min(len)
+}
+
+// The offset counter t of the block at `idx`, the number of bytes of an
+// input of `len` bytes up to the end of the block
+fn counter(idx: usize, len: usize) -> [u64; 2] {
+    let t = ((idx + 1) * BLOCK_BYTES).min(len) as u128;
+    [t as u64, (t >> 64) as u64]
+}
+
+/// Hashes byte inputs of a length fixed at synthesis with BLAKE2b-512. Every
+/// block takes a compression of `Blake2fChip`, which has to be configured
+/// with `max_rounds` at least 12, the rounds of BLAKE2b, as a compression
+/// lays out all of them.
+#[derive(Clone, Debug)]
+pub struct Blake2bChip<F: FieldExt> {
+    chip: Blake2fChip<F>,
+}
+
+impl<F: FieldExt> Blake2bChip<F> {
+    /// # Panics
+    ///
+    /// Panics if the chip is configured with fewer than 12 rounds.
+    pub fn construct(config: Blake2fConfig<F>) -> Self {
+        let max_rounds = config.max_rounds();
+        assert!(max_rounds >= ROUNDS, "{} rounds for a compression of {}", max_rounds, ROUNDS);
+        Self {
+            chip: Blake2fChip::construct(config, vec![]),
+        }
+    }
+
+    pub fn load(&self, layouter: &mut impl Layouter<F>) -> Result<(), Error> {
+        self.chip.load(layouter)
+    }
+
+    /// Returns the 64 bytes of the BLAKE2b-512 digest of `input`, each the
+    /// byte of an output word.
+    pub fn hash(
+        &self,
+        layouter: &mut impl Layouter<F>,
+        input: &[AssignedByte<F>],
+    ) -> Result<[AssignedByte<F>; OUTBYTES], Error> {
+        let digest = self.hash_with_params(layouter, &ParamBlock::default(), &[], input)?;
+        Ok(digest.try_into().unwrap())
+    }
+
+    /// Returns the 64 bytes of the BLAKE2b-512 MAC of `input` under `key`,
+    /// a secret of 1 to `KEYBYTES` bytes, to be checked against a tag.
+    pub fn mac(
+        &self,
+        layouter: &mut impl Layouter<F>,
+        key: &[AssignedByte<F>],
+        input: &[AssignedByte<F>],
+    ) -> Result<[AssignedByte<F>; OUTBYTES], Error> {
+        if key.is_empty() {
+            return Err(Error::Synthesis);
+        }
+        let params = ParamBlock::keyed(key.len()).ok_or(Error::Synthesis)?;
+        let tag = self.hash_with_params(layouter, &params, key, input)?;
+        Ok(tag.try_into().unwrap())
+    }
+
+    /// Returns the `params.digest_length` bytes of the digest of `input`
+    /// with the parameters `params`, under `key` if it is not empty. The
+    /// parameters are fixed at synthesis, so they are folded into the
+    /// constant initial state rather than witnessed, and the digest is the
+    /// output truncated to its length. The key has to be `params.key_length`
+    /// bytes long.
+    pub fn hash_with_params(
+        &self,
+        layouter: &mut impl Layouter<F>,
+        params: &ParamBlock,
+        key: &[AssignedByte<F>],
+        input: &[AssignedByte<F>],
+    ) -> Result<Vec<AssignedByte<F>>, Error> {
+        if !params.is_valid() || key.len() != params.key_length as usize {
+            return Err(Error::Synthesis);
+        }
+        let output = if key.is_empty() {
+            self.hash_from(layouter, params.initial_state(), input)?
+        } else {
+            let zero = self.chip.compression().assign_constant(layouter, 0)?;
+            let zero = zero.cell().ok_or(Error::Synthesis)?;
+
+            let mut data = key.to_vec();
+            data.resize(BLOCK_BYTES, zero.clone());
+            data.extend_from_slice(input);
+            self.hash_from(layouter, params.initial_state(), &data)?
+        };
+        self.chip.digest(layouter, &output, params.digest_length as usize)
+    }
+
+    // Hashes `input` from the initial state `h`, returning the state after
+    // the final block
+    fn hash_from(
+        &self,
+        layouter: &mut impl Layouter<F>,
+        h: [u64; STATE],
+        input: &[AssignedByte<F>],
+    ) -> Result<State<F>, Error> {
+        let rounds = self.chip.constant_rounds(layouter, ROUNDS as u32)?;
+        let mut state = self.chip.initial_state(layouter, h)?;
+
+        let num_blocks = num_blocks(input.len());
+        for idx in 0..num_blocks {
+            let bytes = &input[block_range(idx, input.len())];
+            let t = counter(idx, input.len());
+            let last = idx == num_blocks - 1;
+
+            let (next, block) = self.chip.compress_block(
+                layouter,
+                &state,
+                &rounds,
+                message_words(bytes),
+                t.map(Value::known),
+                Value::known(last),
+            )?;
+            state = next;
+
+            // t and f are those of the block
+            layouter.assign_region(
+                || "message block",
+                |mut region| {
+                    constrain_message(&mut region, &block, bytes)?;
+                    for (word, t) in block.counter().iter().zip(t) {
+                        let cell = word.cell().ok_or(Error::Synthesis)?;
+                        region.constrain_constant(cell.cell(), F::from(t))?;
+                    }
+                    region.constrain_constant(block.last_block().cell(), F::from(last as u64))
+                },
+            )?;
+        }
+
+        Ok(state)
+    }
+
+    /// Returns the BLAKE2b-512 digest of the first `len` bytes of `input`,
+    /// where `len` is witnessed and at most the length of `input`. The bytes
+    /// past `len` are ignored, whatever they hold. As many blocks as `input`
+    /// fills are compressed, and the digest is the state after the block
+    /// holding the last byte of the message, the first one if it is empty.
+    pub fn hash_with_len(
+        &self,
+        layouter: &mut impl Layouter<F>,
+        input: &[AssignedByte<F>],
+        len: &AssignedCell<F, F>,
+    ) -> Result<[AssignedByte<F>; OUTBYTES], Error> {
+        let compression = self.chip.compression();
+        let rounds = self.chip.constant_rounds(layouter, ROUNDS as u32)?;
+        let mut state = self.chip.initial_state(layouter, ParamBlock::default().initial_state())?;
+        let constant = |layouter: &mut _, value: usize| {
+            let word = compression.assign_constant(layouter, value as u64)?;
+            word.cell().cloned().ok_or(Error::Synthesis)
+        };
+        let (zero, one) = (constant(layouter, 0)?, constant(layouter, 1)?);
+
+        // A flag for every byte of the input, set for the first `len` of
+        // them, which bounds `len` by the length of the input. The bytes of
+        // the message are the input bytes with their flag set, then zeros.
+        let len = compression.assign_word_from(layouter, len)?;
+        let flags = compression.assign_flags(layouter, &len, input.len())?;
+        let len = len.cell().cloned().ok_or(Error::Synthesis)?;
+        let selections = flags
+            .iter()
+            .zip(input)
+            .map(|(flag, byte)| (flag, &zero, byte))
+            .collect::<Vec<_>>();
+        let message = compression
+            .select_cells(layouter, &selections)?
+            .iter()
+            .map(|byte| byte.cell().cloned().ok_or(Error::Synthesis))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // A block is active if the message has a byte in it, and the first
+        // block always is. The last active one is the final block.
+        let num_blocks = num_blocks(input.len());
+        let active = (0..=num_blocks)
+            .map(|idx| match idx {
+                0 => one.clone(),
+                idx => flags.get(idx * BLOCK_BYTES).cloned().unwrap_or_else(|| zero.clone()),
+            })
+            .collect::<Vec<_>>();
+
+        let mut digest: Option<State<F>> = None;
+        for idx in 0..num_blocks {
+            let bytes = &message[block_range(idx, input.len())];
+
+            // f = active[idx] - active[idx + 1], and t is the length for the
+            // final block and the end of the block for the ones before it
+            let end = constant(layouter, (idx + 1) * BLOCK_BYTES)?;
+            let [last, full] = compression
+                .select_cells(
+                    layouter,
+                    &[(&active[idx + 1], &active[idx], &zero), (&active[idx + 1], &zero, &end)],
+                )?
+                .try_into()
+                .unwrap();
+            let (last, full) = (
+                last.cell().cloned().ok_or(Error::Synthesis)?,
+                full.cell().cloned().ok_or(Error::Synthesis)?,
+            );
+            let t = compression.select_cells(layouter, &[(&last, &full, &len)])?.remove(0);
+            let t = t.cell().cloned().ok_or(Error::Synthesis)?;
+
+            let (next, block) = self.chip.compress_block(
+                layouter,
+                &state,
+                &rounds,
+                message_words(bytes),
+                [t.value().map(|t| t.get_lower_128() as u64), Value::known(0)],
+                last.value().map(|last| *last == F::one()),
+            )?;
+
+            layouter.assign_region(
+                || "message block",
+                |mut region| {
+                    constrain_message(&mut region, &block, bytes)?;
+                    let [t_lo, t_hi] = block.counter();
+                    let (t_lo, t_hi) = (
+                        t_lo.cell().ok_or(Error::Synthesis)?,
+                        t_hi.cell().ok_or(Error::Synthesis)?,
+                    );
+                    region.constrain_equal(t_lo.cell(), t.cell())?;
+                    region.constrain_constant(t_hi.cell(), F::zero())?;
+                    region.constrain_equal(block.last_block().cell(), last.cell())
+                },
+            )?;
+
+            // The state after the last active block
+            digest = Some(match digest {
+                None => next.clone(),
+                Some(digest) => compression.select_state(layouter, &active[idx], &digest, &next)?,
+            });
+            state = next;
+        }
+
+        let digest = self.chip.digest(layouter, &digest.ok_or(Error::Synthesis)?, OUTBYTES)?;
+        Ok(digest.try_into().unwrap())
+    }
+}
+
+// The values of the message words of a block made of `bytes`, then zeros
+fn message_words<F: FieldExt>(bytes: &[AssignedByte<F>]) -> [Value<u64>; 16] {
+    let mut m = [Value::known(0); 16];
+    for (offset, byte) in bytes.iter().enumerate() {
+        let byte = byte.value().map(|byte| byte.get_lower_128() as u64);
+        let word = &mut m[offset / 8];
+        *word = word.zip(byte).map(|(word, byte)| word | (byte << (8 * (offset % 8))));
+    }
+    m
+}
+
+// Constrains the message words of `block` to be made of `bytes`, then zeros
+fn constrain_message<F: FieldExt>(
+    region: &mut Region<'_, F>,
+    block: &Block<F>,
+    bytes: &[AssignedByte<F>],
+) -> Result<(), Error> {
+    let chunks = block.message().iter().flat_map(|word| word.chunks().iter());
+    for (offset, chunk) in chunks.enumerate() {
+        match bytes.get(offset) {
+            Some(byte) => region.constrain_equal(byte.cell(), chunk.cell())?,
+            None => region.constrain_constant(chunk.cell(), F::zero())?,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers_core::utils::hex::FromHex;
+    use halo2_proofs::{
+        circuit::{Layouter, Region, SimpleFloorPlanner, Value},
+        dev::MockProver,
+        halo2curves::bn256::Fr,
+        plonk::{Advice, Circuit, Column, ConstraintSystem, Error, FirstPhase, Instance},
+    };
+
+    use super::{
+        blake2b, blake2b_mac, blake2b_with_params, Blake2bChip, ParamBlock, KEYBYTES, OUTBYTES,
+    };
+    use crate::blake2f::{Blake2fConfig, Blake2fTable, ROUNDS};
+
+    // https://www.rfc-editor.org/rfc/rfc7693#appendix-A and the BLAKE2b-512
+    // digests of the empty string and of the usual pangram
+    fn vectors() -> Vec<(Vec<u8>, Vec<u8>)> {
+        [
+            (
+                "",
+                "786a02f742015903c6c6fd852552d272912f4740e15847618a86e217f71f5419d25e1031afee585313896444934eb04b903a685b1448b755d56f701afe9be2ce",
+            ),
+            (
+                "abc",
+                "ba80a53f981c4d0d6a2797b69f12f6e94c212f14685ac4b74b12bb6fdbffa2d17d87c5392aab792dc252d5de4533cc9518d38aa8dbf1925ab92386edd4009923",
+            ),
+            (
+                "The quick brown fox jumps over the lazy dog",
+                "a8add4bdddfd93e4877d2746e62817b116364a1fa7bc148d95090bc7333b3673f82401cf7aa2e4cb1ecd90296e3f14cb5413f8ed77be73045b13914cdcd6a918",
+            ),
+        ]
+        .into_iter()
+        .map(|(input, digest)| (input.as_bytes().to_vec(), Vec::from_hex(digest).unwrap()))
+        .collect()
+    }
+
+    // The bytes 0, 1, ..., 199, over two blocks
+    fn two_blocks() -> (Vec<u8>, Vec<u8>) {
+        let input = (0..200).collect();
+        let digest = "fb3c1f0f56a56f8e316fdf5d853c8c872c39635d083634c3904fc3ac07d1b578e85ff0e480e92d44ade33b62e893ee32343e79ddf6ef292e89b582d312502314";
+        (input, Vec::from_hex(digest).unwrap())
+    }
+
+    // Hashes its input, or its first `len` bytes with the length witnessed,
+    // and exposes the digest
+    #[derive(Default)]
+    struct HashCircuit {
+        input: Vec<u8>,
+        len: Option<usize>,
+        key: Option<Vec<u8>>,
+        params: Option<ParamBlock>,
+    }
+
+    #[derive(Clone)]
+    struct HashConfig {
+        blake2f: Blake2fConfig<Fr>,
+        byte: Column<Advice>,
+        instance: Column<Instance>,
+    }
+
+    impl HashCircuit {
+        // The instance column of the F chip, which the hash leaves empty,
+        // then the digest
+        fn instance(digest: &[u8]) -> Vec<Vec<Fr>> {
+            vec![vec![], digest.iter().map(|byte| Fr::from(*byte as u64)).collect()]
+        }
+    }
+
+    impl Circuit<Fr> for HashCircuit {
+        type Config = HashConfig;
+        type FloorPlanner = SimpleFloorPlanner;
+
+        fn without_witnesses(&self) -> Self {
+            Self {
+                input: self.input.clone(),
+                len: self.len,
+                key: self.key.clone(),
+                params: self.params,
+            }
+        }
+
+        fn configure(meta: &mut ConstraintSystem<Fr>) -> Self::Config {
+            let challenge = meta.challenge_usable_after(FirstPhase);
+            let table = Blake2fTable::construct(meta, challenge);
+            let blake2f = Blake2fConfig::configure(meta, table, ROUNDS, 1);
+            let byte = meta.advice_column();
+            meta.enable_equality(byte);
+            let instance = meta.instance_column();
+            meta.enable_equality(instance);
+            HashConfig {
+                blake2f,
+                byte,
+                instance,
+            }
+        }
+
+        fn synthesize(&self, config: Self::Config, mut layouter: impl Layouter<Fr>) -> Result<(), Error> {
+            let chip = Blake2bChip::construct(config.blake2f);
+            chip.load(&mut layouter)?;
+
+            let assign_bytes = |region: &mut Region<'_, Fr>, bytes: &[u8]| {
+                bytes
+                    .iter()
+                    .enumerate()
+                    .map(|(offset, byte)| {
+                        let byte = Value::known(Fr::from(*byte as u64));
+                        region.assign_advice(|| "byte", config.byte, offset, || byte)
+                    })
+                    .collect::<Result<Vec<_>, _>>()
+            };
+            let (input, len) = layouter.assign_region(
+                || "input",
+                |mut region| {
+                    let input = assign_bytes(&mut region, &self.input)?;
+                    let len = self
+                        .len
+                        .map(|len| {
+                            let len = Value::known(Fr::from(len as u64));
+                            region.assign_advice(|| "len", config.byte, self.input.len(), || len)
+                        })
+                        .transpose()?;
+                    Ok((input, len))
+                },
+            )?;
+            let key = self
+                .key
+                .as_ref()
+                .map(|key| layouter.assign_region(|| "key", |mut region| assign_bytes(&mut region, key)))
+                .transpose()?;
+
+            let digest = match (len, key, self.params) {
+                (Some(len), _, _) => chip.hash_with_len(&mut layouter, &input, &len)?,
+                (None, key, Some(params)) => {
+                    chip.hash_with_params(&mut layouter, &params, &key.unwrap_or_default(), &input)?
+                }
+                (None, Some(key), None) => chip.mac(&mut layouter, &key, &input)?,
+                (None, None, None) => chip.hash(&mut layouter, &input)?,
+            };
+            for (row, byte) in digest.iter().enumerate() {
+                layouter.constrain_instance(byte.cell(), config.instance, row)?;
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_blake2b_native() {
+        for (input, digest) in vectors().into_iter().chain(std::iter::once(two_blocks())) {
+            assert_eq!(blake2b(&input).to_vec(), digest, "input of {} bytes", input.len());
+        }
+    }
+
+    #[test]
+    fn test_blake2b_circuit() {
+        for (input, digest) in vectors() {
+            let circuit = HashCircuit {
+                input,
+                ..Default::default()
+            };
+            let prover = MockProver::run(12, &circuit, HashCircuit::instance(&digest)).unwrap();
+            assert_eq!(prover.verify(), Ok(()), "input of {} bytes", circuit.input.len());
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "11 rounds for a compression of 12")]
+    fn test_blake2b_chip_too_few_rounds() {
+        let mut meta = ConstraintSystem::<Fr>::default();
+        let challenge = meta.challenge_usable_after(FirstPhase);
+        let table = Blake2fTable::construct(&mut meta, challenge);
+        Blake2bChip::construct(Blake2fConfig::configure(&mut meta, table, ROUNDS - 1, 1));
+    }
+
+    #[test]
+    fn test_blake2b_circuit_two_blocks() {
+        let (input, digest) = two_blocks();
+        let circuit = HashCircuit {
+            input,
+            ..Default::default()
+        };
+        let prover = MockProver::run(13, &circuit, HashCircuit::instance(&digest)).unwrap();
+        assert_eq!(prover.verify(), Ok(()));
+    }
+
+    #[test]
+    fn test_blake2b_circuit_wrong_digest() {
+        let circuit = HashCircuit {
+            input: b"abc".to_vec(),
+            ..Default::default()
+        };
+        let mut digest = blake2b(&circuit.input);
+        digest[OUTBYTES - 1] ^= 1;
+        let prover = MockProver::run(12, &circuit, HashCircuit::instance(&digest)).unwrap();
+        assert!(prover.verify().is_err());
+    }
+
+    // Two blocks of input, the bytes 0, 1, ..., 255, of which the first
+    // `len` are hashed
+    fn with_len(len: usize) -> (HashCircuit, Vec<Vec<Fr>>) {
+        let input = (0..=255).collect::<Vec<u8>>();
+        let digest = blake2b(&input[..len]);
+        let circuit = HashCircuit {
+            input,
+            len: Some(len),
+            ..Default::default()
+        };
+        (circuit, HashCircuit::instance(&digest))
+    }
+
+    #[test]
+    fn test_blake2b_circuit_with_len() {
+        // An empty message, one in the first block only, one filling it
+        // exactly, one over both blocks and one filling both
+        for len in [0, 3, 128, 200, 256] {
+            let (circuit, instance) = with_len(len);
+            let prover = MockProver::run(13, &circuit, instance).unwrap();
+            assert_eq!(prover.verify(), Ok(()), "message of {} bytes", len);
+        }
+    }
+
+    #[test]
+    fn test_blake2b_circuit_with_wrong_len() {
+        // The digest of 199 bytes claimed for 200
+        let (mut circuit, instance) = with_len(199);
+        circuit.len = Some(200);
+        let prover = MockProver::run(13, &circuit, instance).unwrap();
+        assert!(prover.verify().is_err());
+    }
+
+    #[test]
+    fn test_blake2b_circuit_with_len_over_input() {
+        // Hashing past the end of the input, as if it were followed by a zero
+        let (mut circuit, _) = with_len(256);
+        circuit.len = Some(257);
+        let mut message = circuit.input.clone();
+        message.push(0);
+        let instance = HashCircuit::instance(&blake2b(&message));
+        let prover = MockProver::run(13, &circuit, instance).unwrap();
+        assert!(prover.verify().is_err());
+    }
+
+    // The keyed vectors of the reference implementation, under the key 0, 1,
+    // ..., 63, of the messages 0, 1, ..., n - 1:
+    // https://github.com/BLAKE2/BLAKE2/blob/master/testvectors/blake2b-kat.txt
+    fn keyed_vectors() -> Vec<(Vec<u8>, Vec<u8>, Vec<u8>)> {
+        [
+            (0, "10ebb67700b1868efb4417987acf4690ae9d972fb7a590c2f02871799aaa4786b5e996e8f0f4eb981fc214b005f42d2ff4233499391653df7aefcbc13fc51568"),
+            (1, "961f6dd1e4dd30f63901690c512e78e4b45e4742ed197c3c5e45c549fd25f2e4187b0bc9fe30492b16b0d0bc4ef9b0f34c7003fac09a5ef1532e69430234cebd"),
+            (3, "33d0825dddf7ada99b0e7e307104ad07ca9cfd9692214f1561356315e784f3e5a17e364ae9dbb14cb2036df932b77f4b292761365fb328de7afdc6d8998f5fc1"),
+            (128, "72065ee4dd91c2d8509fa1fc28a37c7fc9fa7d5b3f8ad3d0d7a25626b57b1b44788d4caf806290425f9890a3a2a35a905ab4b37acfd0da6e4517b2525c9651e4"),
+            (255, "142709d62e28fcccd0af97fad0f8465b971e82201dc51070faa0372aa43e92484be1c1e73ba10906d5d1853db6a4106e0a7bf9800d373d6dee2d46d62ef2a461"),
+        ]
+        .into_iter()
+        .map(|(len, tag)| {
+            let key = (0..KEYBYTES as u8).collect();
+            let input = (0..len as u8).collect();
+            (key, input, Vec::from_hex(tag).unwrap())
+        })
+        .collect()
+    }
+
+    fn mac_circuit(key: Vec<u8>, input: Vec<u8>) -> HashCircuit {
+        HashCircuit {
+            input,
+            key: Some(key),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_blake2b_mac_native() {
+        for (key, input, tag) in keyed_vectors() {
+            assert_eq!(blake2b_mac(&key, &input).to_vec(), tag, "message of {} bytes", input.len());
+        }
+        // A one-byte key
+        let tag = "aa65cf292e7df1f7439b350072d55485083ccf55b149a400c8c0548233f46447d9f95242a31bf783081c997a6c26e086bc8c0f363dd0c03e8f8edfae0c4aa5ca";
+        assert_eq!(blake2b_mac(b"k", b"abc").to_vec(), Vec::from_hex(tag).unwrap());
+
+        // Key lengths over 64 bytes, including those that wrap around a
+        // byte to a valid one
+        assert_eq!(ParamBlock::keyed(KEYBYTES).map(|params| params.key_length), Some(64));
+        for len in [KEYBYTES + 1, 256, 320] {
+            assert_eq!(ParamBlock::keyed(len), None, "key of {} bytes", len);
+        }
+    }
+
+    #[test]
+    fn test_blake2b_mac_circuit() {
+        // The key block alone, then the key block and a message block
+        for (k, idx) in [(12, 0), (13, 2)] {
+            let (key, input, tag) = keyed_vectors().swap_remove(idx);
+            let circuit = mac_circuit(key, input);
+            let prover = MockProver::run(k, &circuit, HashCircuit::instance(&tag)).unwrap();
+            assert_eq!(prover.verify(), Ok(()), "message of {} bytes", circuit.input.len());
+        }
+    }
+
+    #[test]
+    fn test_blake2b_mac_circuit_wrong_key() {
+        let (mut key, input, tag) = keyed_vectors().swap_remove(0);
+        key[0] ^= 1;
+        let circuit = mac_circuit(key, input);
+        let prover = MockProver::run(12, &circuit, HashCircuit::instance(&tag)).unwrap();
+        assert!(prover.verify().is_err());
+    }
+
+    #[test]
+    fn test_blake2b_mac_circuit_key_length() {
+        // A key of 64 bytes is the longest there is, and keys of no byte and
+        // of more than 64 bytes do not synthesize
+        let key = vec![0; KEYBYTES];
+        let circuit = mac_circuit(key.clone(), vec![]);
+        let instance = HashCircuit::instance(&blake2b_mac(&key, &[]));
+        let prover = MockProver::run(12, &circuit, instance.clone()).unwrap();
+        assert_eq!(prover.verify(), Ok(()));
+
+        for len in [0, KEYBYTES + 1, 320] {
+            let circuit = mac_circuit(vec![0; len], vec![]);
+            let result = MockProver::run(12, &circuit, instance.clone());
+            assert!(matches!(result, Err(Error::Synthesis)), "key of {} bytes", len);
+        }
+    }
+
+    // The digests of "abc" with Python's hashlib.blake2b: salted and
+    // personalized as Zcash does, the same under the key "key", and with a
+    // fanout of 2 and a depth of 3
+    fn param_vectors() -> Vec<(ParamBlock, Vec<u8>, Vec<u8>)> {
+        let salted = ParamBlock {
+            salt: *b"0123456789abcdef",
+            personal: *b"ZcashPrevoutHash",
+            ..Default::default()
+        };
+        [
+            (
+                salted,
+                vec![],
+                "574d8433b85141387c7684bf854fe8a2f743d7ed15ef992e17fc9e52d366b2750edbd31e226fdb1e0d92f87d41f685dfa984dfab7d3dfa72cf485b4b7b0fcf07",
+            ),
+            (
+                ParamBlock {
+                    key_length: 3,
+                    ..salted
+                },
+                b"key".to_vec(),
+                "787af15539939108c3061414547d112c7bcd21c81b45b7933fd2d7ab3a64a2116c461ee6154d737434799d64a31b5df2e9db14e048d12c5ee20d2517d0b9202f",
+            ),
+            (
+                ParamBlock {
+                    fanout: 2,
+                    depth: 3,
+                    ..Default::default()
+                },
+                vec![],
+                "57deae5a08b02978b438804e5cbc16f05c1725210e38b1c048136c5ca2534a0bee460698d5efba8eae57281b1be5c1b7de8ea94cfb88bb31d45fa4ed1091d19a",
+            ),
+        ]
+        .into_iter()
+        .map(|(params, key, digest)| (params, key, Vec::from_hex(digest).unwrap()))
+        .collect()
+    }
+
+    fn params_circuit(params: ParamBlock, key: Vec<u8>) -> HashCircuit {
+        HashCircuit {
+            input: b"abc".to_vec(),
+            key: Some(key),
+            params: Some(params),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_param_block() {
+        assert_eq!(ParamBlock::default().to_bytes()[..8], [64, 0, 1, 1, 0, 0, 0, 0]);
+        assert_eq!(ParamBlock::default().initial_state()[0], 0x6a09e667f3bcc908 ^ 0x0101_0040);
+        for (params, key, digest) in param_vectors() {
+            assert_eq!(blake2b_with_params(&params, &key, b"abc").to_vec(), digest, "{:?}", params);
+        }
+    }
+
+    #[test]
+    fn test_blake2b_params_circuit() {
+        // The keyed vector hashes a key block and a message block
+        for ((params, key, digest), k) in param_vectors().into_iter().zip([12, 13, 12]) {
+            let circuit = params_circuit(params, key);
+            let prover = MockProver::run(k, &circuit, HashCircuit::instance(&digest)).unwrap();
+            assert_eq!(prover.verify(), Ok(()), "{:?}", params);
+        }
+    }
+
+    #[test]
+    fn test_blake2b_params_circuit_wrong_personal() {
+        let (mut params, key, digest) = param_vectors().swap_remove(0);
+        params.personal[0] ^= 1;
+        let circuit = params_circuit(params, key);
+        let prover = MockProver::run(12, &circuit, HashCircuit::instance(&digest)).unwrap();
+        assert!(prover.verify().is_err());
+    }
+
+    #[test]
+    fn test_blake2b_params_circuit_invalid() {
+        // A key of another length than the parameters give, and digests of
+        // no byte and of more than 64 bytes, do not synthesize, while the
+        // same circuits with valid parameters do
+        let (params, key, digest) = param_vectors().swap_remove(1);
+        let digest_length = |digest_length| ParamBlock {
+            digest_length,
+            ..Default::default()
+        };
+        let circuit = params_circuit(params, key);
+        let prover = MockProver::run(13, &circuit, HashCircuit::instance(&digest)).unwrap();
+        assert_eq!(prover.verify(), Ok(()));
+
+        let invalid = [(params, b"k".to_vec()), (digest_length(0), vec![]), (digest_length(65), vec![])];
+        for (params, key) in invalid {
+            let circuit = params_circuit(params, key);
+            let result = MockProver::run(13, &circuit, HashCircuit::instance(&digest));
+            assert!(matches!(result, Err(Error::Synthesis)), "{:?}", params);
+        }
+    }
+
+    // The BLAKE2b-160, -224, -256 and -512 digests of "abc" with Python's
+    // hashlib.blake2b, and the BLAKE2b-256 digest personalized as Zcash does
+    fn digest_length_vectors() -> Vec<(ParamBlock, Vec<u8>)> {
+        [
+            (20, "384264f676f39536840523f284921cdc68b6846b"),
+            (28, "9bd237b02a29e43bdd6738afa5b53ff0eee178d6210b618e4511aec8"),
+            (32, "bddd813c634239723171ef3fee98579b94964e3bb1cb3e427262c8c068d52319"),
+            (64, "ba80a53f981c4d0d6a2797b69f12f6e94c212f14685ac4b74b12bb6fdbffa2d17d87c5392aab792dc252d5de4533cc9518d38aa8dbf1925ab92386edd4009923"),
+        ]
+        .into_iter()
+        .map(|(len, digest)| (ParamBlock::with_digest_length(len).unwrap(), digest))
+        .chain(std::iter::once((
+            ParamBlock {
+                personal: *b"ZcashPrevoutHash",
+                ..ParamBlock::with_digest_length(32).unwrap()
+            },
+            "8382ae6e7b437cb42fc0746376450b7730e3b4e4da7f1d40fbd71d26797f2377",
+        )))
+        .map(|(params, digest)| (params, Vec::from_hex(digest).unwrap()))
+        .collect()
+    }
+
+    #[test]
+    fn test_blake2b_digest_length() {
+        for (params, digest) in digest_length_vectors() {
+            assert_eq!(blake2b_with_params(&params, &[], b"abc"), digest, "{:?}", params);
+        }
+        // A one-byte digest, which differs from the first byte of the others
+        let params = ParamBlock::with_digest_length(1).unwrap();
+        assert_eq!(blake2b_with_params(&params, &[], b"abc"), [0x6b]);
+
+        // Lengths out of range, including those that wrap around a byte to
+        // a valid one
+        for len in [0, OUTBYTES + 1, 256, 256 + OUTBYTES, 320] {
+            assert_eq!(ParamBlock::with_digest_length(len), None, "digest of {} bytes", len);
+        }
+    }
+
+    #[test]
+    fn test_blake2b_digest_length_circuit() {
+        for (params, digest) in digest_length_vectors() {
+            let circuit = params_circuit(params, vec![]);
+            let prover = MockProver::run(12, &circuit, HashCircuit::instance(&digest)).unwrap();
+            assert_eq!(prover.verify(), Ok(()), "{:?}", params);
+        }
+    }
+
+    #[test]
+    fn test_blake2b_digest_length_circuit_wrong_digest() {
+        // The BLAKE2b-256 digest claimed to be the BLAKE2b-512 one truncated
+        let (params, _) = digest_length_vectors().swap_remove(2);
+        let digest = blake2b(b"abc");
+        let circuit = params_circuit(params, vec![]);
+        let prover = MockProver::run(12, &circuit, HashCircuit::instance(&digest[..32])).unwrap();
+        assert!(prover.verify().is_err());
+    }
+}
@@ -1,73 +1,144 @@
 // implementation of blake2 hashing algorithm with halo2
-// this is a basic implementation with no optional features such as salting, personalized hashes, or tree hashing
-#![allow(dead_code)]
-#![allow(unused_variables)]
-#![allow(unreachable_code)]
+// this is a basic implementation with no optional features such as tree hashing;
+// keyed, salted and personalized hashing is built on top of it in the blake2b module
 
 use std::marker::PhantomData;
-use bitvec::prelude::*;
+use ethers_core::types::H512;
 
-use pasta_curves::pallas::Base;
-
-pub struct BlockWord(pub Option<u32>);
+pub struct BlockWord(pub Value<u64>);
 
 use halo2_proofs::{
     arithmetic::FieldExt,
-    circuit::{Layouter, Chip},
-    plonk::{Advice, Any, Column, ConstraintSystem, Error}, 
+    circuit::{AssignedCell, Layouter, Value},
+    plonk::{Column, ConstraintSystem, Error, Instance},
 };
 
 
 use crate::compression::*;
+use crate::utils::rlc;
+
+mod call;
+mod slots;
+mod table;
 
+pub use call::{AssignedCall, CallConfig};
+pub use slots::{RoundSlotRow, RoundSlotsCall, RoundSlotsConfig};
+pub use table::{Blake2fTable, Blake2fTableRow, RlcConfig};
 
-// we use 12 rounds for BLAKE2
-const ROUNDS: usize = 12;
+
+// BLAKE2b uses 12 rounds, although EIP-152 lets the caller pick any number
+pub(crate) const ROUNDS: usize = 12;
 //const STATE: usize = 8;
 const BLOCK_SIZE: usize = 16; //check?
-const DIGEST_SIZE: usize = 8; //check?
+const DIGEST_SIZE: usize = 8;
+// rounds, h, m, t, f and the output of a compression in public mode
+const PUBLIC_INPUTS: usize = 1 + 8 + BLOCK_SIZE + 2 + 1 + DIGEST_SIZE;
 
 
 
-#[derive(Clone, Debug)]
-pub struct Blake2fTable {
-    id: Column<Advice>,
-}
-
-impl Blake2fTable {
-    pub fn construct<F: FieldExt>(meta: &mut ConstraintSystem<F>) -> Self {
-        Self {
-            id: meta.advice_column(),
-        }
-    }
-
-    pub fn columns(&self) -> Vec<Column<Any>> {
-        vec![self.id.into()]
-    }
-
-    pub fn annotations(&self) -> Vec<String> {
-        vec![String::from("id")]
-    }
-}
-
 #[derive(Clone, Debug)]
 pub struct Blake2fConfig<F> {
     table: Blake2fTable,
     _marker: PhantomData<F>,
     compression: CompressionConfig,
+    rlc: RlcConfig,
+    call: CallConfig,
+    slots: RoundSlotsConfig,
+    instance: Column<Instance>,
+    max_rounds: usize,
+    max_calls: usize,
+    public_io: bool,
 }
 
 impl<F: FieldExt> Blake2fConfig<F> {
-    pub fn configure(meta: &mut ConstraintSystem<F>, table: Blake2fTable, compression: CompressionConfig) -> Self {
+    /// Configures the chip to lay out `max_calls` compressions sharing
+    /// `max_rounds` rounds between them, each taking only as many as the
+    /// caller asked for. Only the number of calls and the number of rounds of
+    /// every compression are public.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_calls` is 0, as the chip lays out at least one call.
+    pub fn configure(
+        meta: &mut ConstraintSystem<F>,
+        table: Blake2fTable,
+        max_rounds: usize,
+        max_calls: usize,
+    ) -> Self {
+        Self::configure_inner(meta, table, max_rounds, max_calls, false)
+    }
+
+    /// Same as `configure`, but every input and output of a compression is
+    /// public, in the order of `Blake2fWitness::public_inputs`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_calls` is 0.
+    pub fn configure_with_public_io(
+        meta: &mut ConstraintSystem<F>,
+        table: Blake2fTable,
+        max_rounds: usize,
+        max_calls: usize,
+    ) -> Self {
+        Self::configure_inner(meta, table, max_rounds, max_calls, true)
+    }
+
+    fn configure_inner(
+        meta: &mut ConstraintSystem<F>,
+        table: Blake2fTable,
+        max_rounds: usize,
+        max_calls: usize,
+        public_io: bool,
+    ) -> Self {
+        // The round slots start from the first call when there is no round
+        assert!(max_calls > 0, "the chip lays out at least one call");
+        let compression = CompressionConfig::configure(meta);
+        let rlc = RlcConfig::configure(meta, table.challenge);
+        let call = CallConfig::configure(meta, table.challenge);
+        let slots = RoundSlotsConfig::configure(meta);
+        let instance = meta.instance_column();
+        meta.enable_equality(instance);
         Self {
             table,
             _marker: PhantomData,
             compression,
-        };
+            rlc,
+            call,
+            slots,
+            instance,
+            max_rounds,
+            max_calls,
+            public_io,
+        }
+    }
+
+    /// Lays out the rotations by 32, 24 and 16 on rows of their own, as they
+    /// were before being rewired, to measure the rows rewiring saves. The
+    /// compressions are the same either way.
+    pub fn with_byte_rotations_laid_out(mut self) -> Self {
+        self.compression.lay_out_byte_rotations();
+        self
+    }
+
+    /// The number of rounds the chip has room for.
+    pub fn max_rounds(&self) -> usize {
+        self.max_rounds
     }
 
+    // The instance row holding the number of rounds of the compression at
+    // `idx`, after the number of calls on row 0
+    fn rounds_row(&self, idx: usize) -> usize {
+        if self.public_io {
+            1 + idx * PUBLIC_INPUTS
+        } else {
+            1 + idx
+        }
+    }
 }
 
+/// The inputs of a compression. In the circuit, every word of h, m and t is
+/// range-checked by its decomposition into bytes, the number of rounds by
+/// its decomposition into four bytes, and f is constrained to be boolean.
 #[derive(Clone, Debug, Default)]
 pub struct Blake2fWitness {
     pub rounds: u32,
@@ -77,18 +148,550 @@ pub struct Blake2fWitness {
     pub f: bool,
 }
 
+/// The length of the input of the BLAKE2 F precompile: 4 bytes of rounds,
+/// 64 of h, 128 of m, 16 of t and the final block flag.
+pub const EIP152_INPUT_LENGTH: usize = 213;
+
+/// The gas EIP-152 charges for each round of the compression.
+pub const EIP152_GAS_PER_ROUND: u64 = 1;
+
+/// An input of the BLAKE2 F precompile that EIP-152 rejects.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Eip152Error {
+    /// The input is not `EIP152_INPUT_LENGTH` bytes long.
+    InvalidLength(usize),
+    /// The final block flag is neither 0 nor 1.
+    InvalidFinalBlockFlag(u8),
+}
+
+impl std::fmt::Display for Eip152Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidLength(length) => write!(
+                f,
+                "input is {} bytes long instead of {}",
+                length, EIP152_INPUT_LENGTH
+            ),
+            Self::InvalidFinalBlockFlag(flag) => {
+                write!(f, "final block flag is {} instead of 0 or 1", flag)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Eip152Error {}
+
+impl Blake2fWitness {
+    /// Decodes the input of the BLAKE2 F precompile: the number of rounds as
+    /// a big-endian u32, then h, m and t as little-endian u64 words, then
+    /// the final block flag as a single byte.
+    pub fn from_eip152_input(input: &[u8]) -> Result<Self, Eip152Error> {
+        if input.len() != EIP152_INPUT_LENGTH {
+            return Err(Eip152Error::InvalidLength(input.len()));
+        }
+        let f = match input[EIP152_INPUT_LENGTH - 1] {
+            0 => false,
+            1 => true,
+            flag => return Err(Eip152Error::InvalidFinalBlockFlag(flag)),
+        };
+
+        let mut words = input[4..EIP152_INPUT_LENGTH - 1]
+            .chunks(8)
+            .map(|bytes| u64::from_le_bytes(bytes.try_into().expect("8-byte chunk")));
+        let mut witness = Self {
+            rounds: u32::from_be_bytes(input[..4].try_into().expect("4-byte rounds")),
+            f,
+            ..Default::default()
+        };
+        for word in witness
+            .h
+            .iter_mut()
+            .chain(witness.m.iter_mut())
+            .chain(witness.t.iter_mut())
+        {
+            *word = words.next().expect("26 words");
+        }
+        Ok(witness)
+    }
+
+    /// Returns the gas EIP-152 charges for the compression.
+    pub fn gas(&self) -> u64 {
+        self.rounds as u64 * EIP152_GAS_PER_ROUND
+    }
+
+    /// Computes the output of the compression natively.
+    pub fn compress(&self) -> H512 {
+        let words = compress_native(self.rounds, self.h, self.m, self.t, self.f);
+        let mut output = H512::zero();
+        for (bytes, word) in output.0.chunks_mut(8).zip(words) {
+            bytes.copy_from_slice(&word.to_le_bytes());
+        }
+        output
+    }
+
+    /// Encodes the witness as an input of the BLAKE2 F precompile, the
+    /// inverse of `from_eip152_input`.
+    pub fn to_eip152_input(&self) -> Vec<u8> {
+        let mut input = Vec::with_capacity(EIP152_INPUT_LENGTH);
+        input.extend_from_slice(&self.rounds.to_be_bytes());
+        for word in self.h.iter().chain(self.m.iter()).chain(self.t.iter()) {
+            input.extend_from_slice(&word.to_le_bytes());
+        }
+        input.push(self.f as u8);
+        input
+    }
+
+    /// Returns the public inputs of the compression of this witness into
+    /// `output` in public mode: the number of rounds, then h, m, t, f and the
+    /// output words.
+    pub fn public_inputs<F: FieldExt>(&self, output: &H512) -> Vec<F> {
+        std::iter::once(self.rounds as u64)
+            .chain(self.h)
+            .chain(self.m)
+            .chain(self.t)
+            .chain(std::iter::once(self.f as u64))
+            .chain(output_words(output))
+            .map(F::from)
+            .collect()
+    }
+}
+
+/// A call to the BLAKE2 F precompile as the EVM makes it, with its
+/// calldata, which may make it fail, and the witness it is compressed with.
+/// A call of the wrong length is compressed with the default witness, and
+/// one with an invalid final block flag byte with its decoded calldata and f
+/// false. Either way, a failed call applies none of its rounds.
+#[derive(Clone, Debug, Default)]
+pub struct Blake2fCall {
+    pub calldata: Vec<u8>,
+    pub witness: Blake2fWitness,
+}
+
+impl Blake2fCall {
+    /// Decodes the calldata of a call, which fails if EIP-152 rejects it as
+    /// an input.
+    pub fn from_calldata(calldata: &[u8]) -> Self {
+        let witness = match Blake2fWitness::from_eip152_input(calldata) {
+            Err(Eip152Error::InvalidFinalBlockFlag(_)) => {
+                let mut input = calldata.to_vec();
+                input[EIP152_INPUT_LENGTH - 1] = 0;
+                Blake2fWitness::from_eip152_input(&input).expect("valid final block flag")
+            }
+            witness => witness.unwrap_or_default(),
+        };
+        Self {
+            calldata: calldata.to_vec(),
+            witness,
+        }
+    }
+
+    pub fn length(&self) -> usize {
+        self.calldata.len()
+    }
+
+    /// Returns the final block flag byte, the last byte of the calldata if
+    /// it is `EIP152_INPUT_LENGTH` bytes long and 0 otherwise.
+    pub fn flag(&self) -> u8 {
+        if self.length() == EIP152_INPUT_LENGTH {
+            self.calldata[EIP152_INPUT_LENGTH - 1]
+        } else {
+            0
+        }
+    }
+
+    pub fn success(&self) -> bool {
+        self.length() == EIP152_INPUT_LENGTH && self.flag() <= 1
+    }
+
+    /// Returns the number of rounds the compression of the call applies,
+    /// none if it fails.
+    pub fn applied_rounds(&self) -> u32 {
+        if self.success() {
+            self.witness.rounds
+        } else {
+            0
+        }
+    }
+
+    /// Computes the output of the compression of the call natively, which is
+    /// that of its witness in no rounds if it fails.
+    pub fn output(&self) -> H512 {
+        Blake2fWitness {
+            rounds: self.applied_rounds(),
+            ..self.witness.clone()
+        }
+        .compress()
+    }
+}
+
+impl From<Blake2fWitness> for Blake2fCall {
+    fn from(witness: Blake2fWitness) -> Self {
+        Self {
+            calldata: witness.to_eip152_input(),
+            witness,
+        }
+    }
+}
+
+/// Returns the instance column of a chip in public mode compressing
+/// `inputs` into `outputs`, with room for `max_calls` calls. The unused
+/// slots hold the compression of the default witness. The output of a
+/// failed call is the one `Blake2fCall::output` computes, in no rounds.
+pub fn public_inputs<F: FieldExt>(inputs: &[Blake2fWitness], outputs: &[H512], max_calls: usize) -> Vec<F> {
+    let padding = Blake2fWitness::default();
+    let padding = (&padding, padding.compress());
+    let calls = inputs.iter().zip(outputs.iter().copied());
+
+    std::iter::once(F::from(inputs.len() as u64))
+        .chain(
+            calls
+                .chain(std::iter::repeat(padding))
+                .take(max_calls)
+                .flat_map(|(input, output)| input.public_inputs(&output)),
+        )
+        .collect()
+}
+
+/// Splits a 64-byte compression output into its eight little-endian words.
+pub fn output_words(output: &H512) -> [u64; DIGEST_SIZE] {
+    let mut words = [0; DIGEST_SIZE];
+    for (word, bytes) in words.iter_mut().zip(output.as_bytes().chunks(8)) {
+        *word = u64::from_le_bytes(bytes.try_into().expect("8-byte chunk"));
+    }
+    words
+}
+
 #[derive(Clone, Debug)]
 pub struct Blake2fChip<F: FieldExt> {
     config: Blake2fConfig<F>,
-    data: Vec<Blake2fWitness>,
+    calls: Vec<Blake2fCall>,
 }
 
 impl<F: FieldExt> Blake2fChip<F> {
     pub fn construct(config: Blake2fConfig<F>, data: Vec<Blake2fWitness>) -> Self {
-        Self { config, data }
+        Self::construct_with_calls(config, data.into_iter().map(Blake2fCall::from).collect())
+    }
+
+    /// Constructs the chip for calls that may fail, whose success bit is on
+    /// their row of the table. A failed call still has its compression laid
+    /// out, as every call slot has the same rows, but it applies no round,
+    /// so it takes none of the `max_rounds` round slots.
+    pub fn construct_with_calls(config: Blake2fConfig<F>, calls: Vec<Blake2fCall>) -> Self {
+        Self { config, calls }
     }
 
     pub fn load(&self, layouter: &mut impl Layouter<F>) -> Result<(), Error> {
+        self.config.compression.load(layouter)
+    }
+
+    pub(crate) fn compression(&self) -> &CompressionConfig {
+        &self.config.compression
+    }
+
+    /// Lays out one compression per call, padded with failed calls of the
+    /// default witness up to `max_calls`, returning the output state of each
+    /// call in order, and fills the table with them. The number of calls is
+    /// public, on row 0 of the instance column, and only that many are given
+    /// an id in the table. The number of rounds of the i-th compression is on
+    /// row 1 + i, or the whole compression is on rows 1 + i * 36 to
+    /// 1 + i * 36 + 35 in public mode.
+    ///
+    /// The rounds of all compressions are laid out one after the other in
+    /// the `max_rounds` round slots of the chip, each compression taking as
+    /// many of them as it has rounds, or none if its call fails.
+    pub fn assign(&self, layouter: &mut impl Layouter<F>) -> Result<Vec<State<F>>, Error> {
+        if self.calls.len() > self.config.max_calls {
+            return Err(Error::Synthesis);
+        }
+        // The layout only has room for `max_rounds` rounds in total
+        let total_rounds = self.calls.iter().map(|call| call.applied_rounds() as u64).sum::<u64>();
+        if total_rounds > self.config.max_rounds as u64 {
+            return Err(Error::Synthesis);
+        }
+
+        // The number of calls is a word read from the instance column just
+        // like a number of rounds, and enables that many slots
+        let count = self.assign_rounds(layouter, 0)?;
+        let enabled = self
+            .config
+            .compression
+            .assign_flags(layouter, &count, self.config.max_calls)?;
+        let padding = Blake2fCall::default();
+        let calls = (0..self.config.max_calls)
+            .map(|idx| self.calls.get(idx).unwrap_or(&padding))
+            .collect::<Vec<_>>();
+
+        // The start of every compression, up to the work vector its rounds
+        // start from, and the one they end with, along with its call
+        let mut starts = Vec::with_capacity(calls.len());
+        let mut slots_calls = Vec::with_capacity(calls.len());
+        for (idx, (call, enabled)) in calls.iter().zip(enabled.iter()).enumerate() {
+            let witness = &call.witness;
+            let rounds = self.assign_rounds(layouter, self.config.rounds_row(idx))?;
+            let state = self
+                .config
+                .compression
+                .assign_state(layouter, witness.h.map(Value::known))?;
+            let (v_init, block) = self.config.compression.initialize(
+                layouter,
+                &state,
+                witness.m.map(Value::known),
+                witness.t.map(Value::known),
+                Value::known(witness.f),
+            )?;
+            let assigned = self.assign_call(layouter, idx + 1, enabled, call, &rounds, &state, &block)?;
+
+            let mut v = work_vector_native(witness.h, witness.t, witness.f);
+            for round in 0..call.applied_rounds() as usize {
+                round_native(&mut v, message_schedule(&witness.m, round));
+            }
+            let v_final = self
+                .config
+                .compression
+                .assign_work_words(layouter, v.map(Value::known))?;
+
+            let schedules = (0..BLAKE2B_SIGMA.len())
+                .map(|round| self.word_rlc(layouter, &message_schedule(block.message(), round)))
+                .collect::<Result<Vec<_>, _>>()?;
+            slots_calls.push(RoundSlotsCall {
+                rounds: assigned.applied_rounds.clone(),
+                rlc_init: self.word_rlc(layouter, &v_init)?,
+                rlc_final: self.word_rlc(layouter, &v_final)?,
+                schedules,
+            });
+            starts.push((assigned, rounds, state, v_init, block, v_final));
+        }
+        let applied = self.config.slots.assign_calls(layouter, &slots_calls)?;
+
+        self.assign_round_slots(layouter, &calls)?;
+
+        // The end of every compression
+        let mut outputs = Vec::with_capacity(calls.len());
+        let mut rows = Vec::with_capacity(calls.len());
+        for (idx, (applied, (call, rounds, state, v_init, block, v_final))) in
+            applied.iter().zip(starts).enumerate()
+        {
+            let output = self
+                .config
+                .compression
+                .finalize(layouter, &state, &v_init, applied, &v_final)?;
+            if self.config.public_io {
+                self.expose(layouter, self.config.rounds_row(idx) + 1, &state, &block, &output)?;
+            }
+
+            rows.push(self.table_row(layouter, call, &rounds, &block, &output)?);
+            outputs.push(output);
+        }
+
+        self.config.table.assign(layouter, &rows)?;
+        outputs.truncate(self.calls.len());
+        Ok(outputs)
+    }
+
+    // Lays out the `max_rounds` round slots: the rounds of every compression
+    // in order, then rounds carrying on from the last one to fill the rest,
+    // which no compression ends with. Without any round at all, they carry on
+    // with the message of the first compression from an all-zero vector.
+    fn assign_round_slots(
+        &self,
+        layouter: &mut impl Layouter<F>,
+        calls: &[&Blake2fCall],
+    ) -> Result<(), Error> {
+        let mut owners = calls
+            .iter()
+            .flat_map(|call| (0..call.applied_rounds() as usize).map(move |round| (*call, round)))
+            .collect::<Vec<_>>();
+        let (last, mut round) = owners
+            .last()
+            .map(|(call, round)| (*call, round + 1))
+            .unwrap_or((calls[0], 0));
+        while owners.len() < self.config.max_rounds {
+            owners.push((last, round));
+            round += 1;
+        }
+
+        let mut v = [0; 16];
+        let mut previous = None;
+        let mut slots = Vec::with_capacity(owners.len());
+        for (call, round) in owners {
+            let witness = &call.witness;
+            let first = round == 0 && round < call.applied_rounds() as usize;
+            if first {
+                v = work_vector_native(witness.h, witness.t, witness.f);
+            }
+            let x = message_schedule(&witness.m, round);
+
+            let (first, v_in) = self.config.compression.assign_work_vector(
+                layouter,
+                Value::known(first),
+                previous.as_ref(),
+                v.map(Value::known),
+            )?;
+            round_native(&mut v, x);
+            let (x, v_out) = self.config.compression.round(layouter, &v_in, x.map(Value::known))?;
+
+            slots.push(RoundSlotRow {
+                first,
+                rlc_in: self.word_rlc(layouter, &v_in)?,
+                rlc_out: self.word_rlc(layouter, &v_out)?,
+                rlc_msg: self.word_rlc(layouter, &x)?,
+            });
+            previous = Some(v_out);
+        }
+
+        self.config.slots.assign_slots(layouter, &slots)
+    }
+
+    // The RLC of a sequence of words, each taken as a whole
+    fn word_rlc(
+        &self,
+        layouter: &mut impl Layouter<F>,
+        words: &[AssignedWord<F>],
+    ) -> Result<AssignedCell<F, F>, Error> {
+        let cells = words
+            .iter()
+            .map(|word| word.cell().cloned().ok_or(Error::Synthesis))
+            .collect::<Result<Vec<_>, _>>()?;
+        self.config.rlc.assign(layouter, &cells)
+    }
+
+    // Decides whether a call succeeds from its length and flag byte, bound
+    // to the RLC of its calldata by that of the first 212 bytes of the input
+    // of its compression, in the order of the precompile
+    #[allow(clippy::too_many_arguments)]
+    fn assign_call(
+        &self,
+        layouter: &mut impl Layouter<F>,
+        slot: usize,
+        enabled: &AssignedCell<F, F>,
+        call: &Blake2fCall,
+        rounds: &AssignedWord<F>,
+        state: &State<F>,
+        block: &Block<F>,
+    ) -> Result<AssignedCall<F>, Error> {
+        // The number of rounds is big-endian, every other word little-endian
+        let mut input = rounds.chunks()[..4].iter().rev().cloned().collect::<Vec<_>>();
+        for word in state.words()?.iter().chain(block.message()).chain(block.counter()) {
+            input.extend(word.chunks().iter().cloned());
+        }
+        let acc = self.config.rlc.assign(layouter, &input)?;
+
+        let r = layouter.get_challenge(self.config.table.challenge);
+        self.config.call.assign(
+            layouter,
+            slot,
+            enabled,
+            Value::known(call.length() as u64),
+            Value::known(call.flag()),
+            r.map(|r| rlc(&call.calldata, r)),
+            rounds.cell().ok_or(Error::Synthesis)?,
+            block.last_block(),
+            &acc,
+        )
+    }
+
+    // The row of the table for a call, with the RLC of its output bytes if
+    // it succeeds
+    fn table_row(
+        &self,
+        layouter: &mut impl Layouter<F>,
+        call: AssignedCall<F>,
+        rounds: &AssignedWord<F>,
+        block: &Block<F>,
+        output: &State<F>,
+    ) -> Result<Blake2fTableRow<F>, Error> {
+        let output = output
+            .words()?
+            .iter()
+            .flat_map(|word| word.chunks().iter().cloned())
+            .collect::<Vec<_>>();
+        let output_rlc = self.config.rlc.assign(layouter, &output)?;
+
+        Ok(Blake2fTableRow {
+            id: call.id,
+            rounds: rounds.cell().cloned().ok_or(Error::Synthesis)?,
+            length: call.length,
+            input_rlc: call.input_rlc,
+            output_rlc: self.config.call.assign_output(layouter, &call.success, &output_rlc)?,
+            f: block.last_block().clone(),
+            flag: call.flag,
+            success: call.success,
+        })
+    }
+
+    /// Assigns the initial state `h` of a hash, every word fixed to its
+    /// constant.
+    pub fn initial_state(
+        &self,
+        layouter: &mut impl Layouter<F>,
+        h: [u64; STATE],
+    ) -> Result<State<F>, Error> {
+        self.config.compression.initialize_with_constants(layouter, h)
+    }
+
+    /// Assigns a number of rounds fixed to `rounds`, which must be at most
+    /// `max_rounds`.
+    pub fn constant_rounds(
+        &self,
+        layouter: &mut impl Layouter<F>,
+        rounds: u32,
+    ) -> Result<AssignedWord<F>, Error> {
+        if rounds as usize > self.config.max_rounds {
+            return Err(Error::Synthesis);
+        }
+        self.config.compression.assign_constant(layouter, rounds as u64)
+    }
+
+    /// Same as `Blake2fInstructions::compress`, but also returns the block
+    /// as assigned, for the caller to constrain its message words, offset
+    /// counter and final block flag.
+    pub fn compress_block(
+        &self,
+        layouter: &mut impl Layouter<F>,
+        state: &State<F>,
+        rounds: &AssignedWord<F>,
+        m: [Value<u64>; BLOCK_SIZE],
+        t: [Value<u64>; 2],
+        f: Value<bool>,
+    ) -> Result<(State<F>, Block<F>), Error> {
+        self.config
+            .compression
+            .compress(layouter, state, rounds, self.config.max_rounds, m, t, f)
+    }
+
+    /// Copies the number of rounds of a compression from `row` of the
+    /// instance column.
+    pub fn assign_rounds(
+        &self,
+        layouter: &mut impl Layouter<F>,
+        row: usize,
+    ) -> Result<AssignedWord<F>, Error> {
+        self.config.compression.assign_rounds(layouter, self.config.instance, row)
+    }
+
+    // Constrains h, m, t, f and the output of a compression to the instance
+    // column, from `row` onwards
+    fn expose(
+        &self,
+        layouter: &mut impl Layouter<F>,
+        row: usize,
+        state: &State<F>,
+        block: &Block<F>,
+        output: &State<F>,
+    ) -> Result<(), Error> {
+        let (h, output) = (state.words()?, output.words()?);
+        let words = h.iter().chain(block.message()).chain(block.counter());
+        let mut cells = words
+            .map(|word| word.cell().cloned().ok_or(Error::Synthesis))
+            .collect::<Result<Vec<_>, _>>()?;
+        cells.push(block.last_block().clone());
+        for word in output.iter() {
+            cells.push(word.cell().cloned().ok_or(Error::Synthesis)?);
+        }
+
+        for (offset, cell) in cells.iter().enumerate() {
+            layouter.constrain_instance(cell.cell(), self.config.instance, row + offset)?;
+        }
         Ok(())
     }
 }
@@ -98,75 +701,100 @@ pub trait Blake2fInstructions<F: FieldExt> {
 
     fn initialization_vector(
         &self,
-        layouter: &mut impl Layouter<Base>,
+        layouter: &mut impl Layouter<F>,
     ) -> Result<Self::State, Error>;
 
     fn initialization(
         &self,
-        layouter: &mut impl Layouter<Base>,
+        layouter: &mut impl Layouter<F>,
         init_state: &Self::State,
     ) -> Result<Self::State, Error>;
 
     fn compress(
         &self,
-        layouter: &mut impl Layouter<Base>,
+        layouter: &mut impl Layouter<F>,
         initialized_state: &Self::State,
+        rounds: &AssignedWord<F>,
         input: [Self::BlockWord; BLOCK_SIZE],
+        counter: [Self::BlockWord; 2],
+        last_block: Value<bool>,
     ) -> Result<Self::State, Error>;
 
+    // The first `digest_length` bytes of the output words of the state,
+    // from 1 to 64
     fn digest(
         &self,
-        layouter: &mut impl Layouter<Base>,
+        layouter: &mut impl Layouter<F>,
         state: &Self::State,
-    ) -> Result<[Self::BlockWord; DIGEST_SIZE], Error>;
+        digest_length: usize,
+    ) -> Result<Vec<AssignedCell<F, F>>, Error>;
 }
 
 
 // here we add the implementation of the BLAKE2 instructions for the BLAKE2 Chip
 impl<F: FieldExt> Blake2fInstructions<F> for Blake2fChip<F> {
-    type State = State;
+    type State = State<F>;
     type BlockWord = BlockWord;
 
     // Used during the first round when we initialize the block with IV
     fn initialization_vector(
         &self,
-        layouter: &mut impl Layouter<Base>,
-    ) -> Result<State, Error> {
-        // replace Ok(State) with call from compression.rs
-        let state = State::initial_state();
-        Ok(state)
+        layouter: &mut impl Layouter<F>,
+    ) -> Result<State<F>, Error> {
+        self.config.compression.initialize_with_iv(layouter)
     }
 
-    // Since the compression algorithm has multiple rounds, we can initialize a table with a previous state
+    // Used for every block after the first one, which starts from the state
+    // the previous compression ended with. The state is copied in, so a chain
+    // of compressions never witnesses its intermediate states again.
     fn initialization(
         &self,
-        layouter: &mut impl Layouter<Base>,
+        layouter: &mut impl Layouter<F>,
         init_state: &Self::State,
-    ) -> Result<State, Error>{
-        // change put just for debugging and running benchmarking
-        let state = State::initial_state(); 
-        Ok(state)
+    ) -> Result<State<F>, Error> {
+        self.config.compression.copy_state(layouter, init_state)
     }
 
     // Given an initialized state and an input message block, compress the
-    // message block and return the final state.
+    // message block in `rounds` rounds and return the final state.
     fn compress(
         &self,
-        layouter: &mut impl Layouter<Base>,
+        layouter: &mut impl Layouter<F>,
         initialized_state: &Self::State,
+        rounds: &AssignedWord<F>,
         input: [Self::BlockWord; BLOCK_SIZE],
+        counter: [Self::BlockWord; 2],
+        last_block: Value<bool>,
     ) -> Result<Self::State, Error> {
-        // change put just for debugging and running benchmarking
-        let state = State::initial_state();
-        Ok(state)
+        self.compress_block(
+            layouter,
+            initialized_state,
+            rounds,
+            input.map(|word| word.0),
+            counter.map(|word| word.0),
+            last_block,
+        )
+        .map(|(state, _)| state)
     }
 
+    // The bytes of the output words are their range-checked chunks, least
+    // significant first, so a digest truncated to any number of bytes is a
+    // prefix of them.
     fn digest(
         &self,
-        layouter: &mut impl Layouter<Base>,
+        layouter: &mut impl Layouter<F>,
         state: &Self::State,
-    ) -> Result<[Self::BlockWord; DIGEST_SIZE], Error> {
-        Ok(BlockWord)
+        digest_length: usize,
+    ) -> Result<Vec<AssignedCell<F, F>>, Error> {
+        if !(1..=DIGEST_SIZE * 8).contains(&digest_length) {
+            return Err(Error::Synthesis);
+        }
+        Ok(state
+            .words()?
+            .iter()
+            .flat_map(|word| word.chunks().clone())
+            .take(digest_length)
+            .collect())
     }
 }
 
@@ -175,57 +803,20 @@ pub mod dev {
     use super::*;
 
     use ethers_core::{types::H512, utils::hex::FromHex};
-    use halo2_proofs::{arithmetic::FieldExt, circuit::SimpleFloorPlanner, plonk::Circuit};
+    use halo2_proofs::{
+        arithmetic::FieldExt,
+        circuit::SimpleFloorPlanner,
+        plonk::{Circuit, FirstPhase},
+    };
     use std::{marker::PhantomData, str::FromStr};
 
     lazy_static::lazy_static! {
         // https://eips.ethereum.org/EIPS/eip-152#example-usage-in-solidity
         pub static ref INPUTS_OUTPUTS: (Vec<Blake2fWitness>, Vec<H512>) = {
-            let (h1, h2) = (
-                <[u8; 32]>::from_hex("48c9bdf267e6096a3ba7ca8485ae67bb2bf894fe72f36e3cf1361d5f3af54fa5").expect(""),
-                <[u8; 32]>::from_hex("d182e6ad7f520e511f6c3e2b8c68059b6bbd41fbabd9831f79217e1319cde05b").expect(""),
-            );
-            let (m1, m2, m3, m4) = (
-                <[u8; 32]>::from_hex("6162630000000000000000000000000000000000000000000000000000000000").expect(""),
-                <[u8; 32]>::from_hex("0000000000000000000000000000000000000000000000000000000000000000").expect(""),
-                <[u8; 32]>::from_hex("0000000000000000000000000000000000000000000000000000000000000000").expect(""),
-                <[u8; 32]>::from_hex("0000000000000000000000000000000000000000000000000000000000000000").expect(""),
-            );
+            let input = Vec::from_hex("0000000c48c9bdf267e6096a3ba7ca8485ae67bb2bf894fe72f36e3cf1361d5f3af54fa5d182e6ad7f520e511f6c3e2b8c68059b6bbd41fbabd9831f79217e1319cde05b61626300000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000300000000000000000000000000000001").expect("");
             (
                 vec![
-                    Blake2fWitness {
-                        rounds: 12,
-                        h: [
-                            u64::from_le_bytes(h1[0x00..0x08].try_into().expect("")),
-                            u64::from_le_bytes(h1[0x08..0x10].try_into().expect("")),
-                            u64::from_le_bytes(h1[0x10..0x18].try_into().expect("")),
-                            u64::from_le_bytes(h1[0x18..0x20].try_into().expect("")),
-                            u64::from_le_bytes(h2[0x00..0x08].try_into().expect("")),
-                            u64::from_le_bytes(h2[0x08..0x10].try_into().expect("")),
-                            u64::from_le_bytes(h2[0x10..0x18].try_into().expect("")),
-                            u64::from_le_bytes(h2[0x18..0x20].try_into().expect("")),
-                        ],
-                        m: [
-                            u64::from_le_bytes(m1[0x00..0x08].try_into().expect("")),
-                            u64::from_le_bytes(m1[0x08..0x10].try_into().expect("")),
-                            u64::from_le_bytes(m1[0x10..0x18].try_into().expect("")),
-                            u64::from_le_bytes(m1[0x18..0x20].try_into().expect("")),
-                            u64::from_le_bytes(m2[0x00..0x08].try_into().expect("")),
-                            u64::from_le_bytes(m2[0x08..0x10].try_into().expect("")),
-                            u64::from_le_bytes(m2[0x10..0x18].try_into().expect("")),
-                            u64::from_le_bytes(m2[0x18..0x20].try_into().expect("")),
-                            u64::from_le_bytes(m3[0x00..0x08].try_into().expect("")),
-                            u64::from_le_bytes(m3[0x08..0x10].try_into().expect("")),
-                            u64::from_le_bytes(m3[0x10..0x18].try_into().expect("")),
-                            u64::from_le_bytes(m3[0x18..0x20].try_into().expect("")),
-                            u64::from_le_bytes(m4[0x00..0x08].try_into().expect("")),
-                            u64::from_le_bytes(m4[0x08..0x10].try_into().expect("")),
-                            u64::from_le_bytes(m4[0x10..0x18].try_into().expect("")),
-                            u64::from_le_bytes(m4[0x18..0x20].try_into().expect("")),
-                        ],
-                        t: [3, 0],
-                        f: true,
-                    }
+                    Blake2fWitness::from_eip152_input(&input).expect("EIP-152 input is well-formed")
                 ],
                 vec![
                     H512::from_str("ba80a53f981c4d0d6a2797b69f12f6e94c212f14685ac4b74b12bb6fdbffa2d17d87c5392aab792dc252d5de4533cc9518d38aa8dbf1925ab92386edd4009923")
@@ -235,6 +826,80 @@ pub mod dev {
         };
     }
 
+    /// A test vector of the BLAKE2 F precompile: its input, and either its
+    /// output or the error the input is rejected with.
+    #[derive(Clone, Debug)]
+    pub struct Eip152Vector {
+        pub input: Vec<u8>,
+        pub output: Result<H512, Eip152Error>,
+    }
+
+    lazy_static::lazy_static! {
+        // https://eips.ethereum.org/EIPS/eip-152#test-cases
+        pub static ref EIP152_VECTORS: Vec<Eip152Vector> = {
+            let vectors: [(&str, Result<&str, Eip152Error>); 9] = [
+                // 0
+                (
+                    "",
+                    Err(Eip152Error::InvalidLength(0)),
+                ),
+                // 1
+                (
+                    "00000c48c9bdf267e6096a3ba7ca8485ae67bb2bf894fe72f36e3cf1361d5f3af54fa5d182e6ad7f520e511f6c3e2b8c68059b6bbd41fbabd9831f79217e1319cde05b61626300000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000300000000000000000000000000000001",
+                    Err(Eip152Error::InvalidLength(212)),
+                ),
+                // 2
+                (
+                    "000000000c48c9bdf267e6096a3ba7ca8485ae67bb2bf894fe72f36e3cf1361d5f3af54fa5d182e6ad7f520e511f6c3e2b8c68059b6bbd41fbabd9831f79217e1319cde05b61626300000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000300000000000000000000000000000001",
+                    Err(Eip152Error::InvalidLength(214)),
+                ),
+                // 3
+                (
+                    "0000000c48c9bdf267e6096a3ba7ca8485ae67bb2bf894fe72f36e3cf1361d5f3af54fa5d182e6ad7f520e511f6c3e2b8c68059b6bbd41fbabd9831f79217e1319cde05b61626300000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000300000000000000000000000000000002",
+                    Err(Eip152Error::InvalidFinalBlockFlag(2)),
+                ),
+                // 4
+                (
+                    "0000000048c9bdf267e6096a3ba7ca8485ae67bb2bf894fe72f36e3cf1361d5f3af54fa5d182e6ad7f520e511f6c3e2b8c68059b6bbd41fbabd9831f79217e1319cde05b61626300000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000300000000000000000000000000000001",
+                    Ok("08c9bcf367e6096a3ba7ca8485ae67bb2bf894fe72f36e3cf1361d5f3af54fa5d282e6ad7f520e511f6c3e2b8c68059b9442be0454267ce079217e1319cde05b"),
+                ),
+                // 5
+                (
+                    "0000000c48c9bdf267e6096a3ba7ca8485ae67bb2bf894fe72f36e3cf1361d5f3af54fa5d182e6ad7f520e511f6c3e2b8c68059b6bbd41fbabd9831f79217e1319cde05b61626300000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000300000000000000000000000000000001",
+                    Ok("ba80a53f981c4d0d6a2797b69f12f6e94c212f14685ac4b74b12bb6fdbffa2d17d87c5392aab792dc252d5de4533cc9518d38aa8dbf1925ab92386edd4009923"),
+                ),
+                // 6
+                (
+                    "0000000c48c9bdf267e6096a3ba7ca8485ae67bb2bf894fe72f36e3cf1361d5f3af54fa5d182e6ad7f520e511f6c3e2b8c68059b6bbd41fbabd9831f79217e1319cde05b61626300000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000300000000000000000000000000000000",
+                    Ok("75ab69d3190a562c51aef8d88f1c2775876944407270c42c9844252c26d2875298743e7f6d5ea2f2d3e8d226039cd31b4e426ac4f2d3d666a610c2116fde4735"),
+                ),
+                // 7
+                (
+                    "0000000148c9bdf267e6096a3ba7ca8485ae67bb2bf894fe72f36e3cf1361d5f3af54fa5d182e6ad7f520e511f6c3e2b8c68059b6bbd41fbabd9831f79217e1319cde05b61626300000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000300000000000000000000000000000001",
+                    Ok("b63a380cb2897d521994a85234ee2c181b5f844d2c624c002677e9703449d2fba551b3a8333bcdf5f2f7e08993d53923de3d64fcc68c034e717b9293fed7a421"),
+                ),
+                // 8
+                (
+                    "ffffffff48c9bdf267e6096a3ba7ca8485ae67bb2bf894fe72f36e3cf1361d5f3af54fa5d182e6ad7f520e511f6c3e2b8c68059b6bbd41fbabd9831f79217e1319cde05b61626300000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000300000000000000000000000000000001",
+                    Ok("fc59093aafa9ab43daae0e914c57635c5402d8e3d2130eb9b3cc181de7f0ecf9b22bf99a7815ce16419e200e01846e6b5df8cc7703041bbceb571de6631d2615"),
+                ),
+            ];
+            vectors
+                .into_iter()
+                .map(|(input, output)| Eip152Vector {
+                    input: Vec::from_hex(input).expect(""),
+                    output: output.map(|output| {
+                        H512::from_str(output).expect("BLAKE2F compression function output is 64-bytes")
+                    }),
+                })
+                .collect()
+        };
+    }
+
+    /// The number of calls the test circuit has room for, sharing its
+    /// `ROUNDS` round slots
+    pub const MAX_CALLS: usize = 2;
+
     #[derive(Default)]
     pub struct Blake2fTestCircuit<F> {
         pub inputs: Vec<Blake2fWitness>,
@@ -251,8 +916,9 @@ pub mod dev {
         }
 
         fn configure(meta: &mut halo2_proofs::plonk::ConstraintSystem<F>) -> Self::Config {
-            let blake2f_table = Blake2fTable::construct(meta);
-            Blake2fConfig::configure(meta, blake2f_table)
+            let challenge = meta.challenge_usable_after(FirstPhase);
+            let blake2f_table = Blake2fTable::construct(meta, challenge);
+            Blake2fConfig::configure_with_public_io(meta, blake2f_table, ROUNDS, MAX_CALLS)
         }
 
         fn synthesize(
@@ -261,17 +927,184 @@ pub mod dev {
             mut layouter: impl Layouter<F>,
         ) -> Result<(), Error> {
             let chip = Blake2fChip::construct(config, self.inputs.clone());
-            chip.load(&mut layouter)
+            chip.load(&mut layouter)?;
+            chip.assign(&mut layouter)?;
+            Ok(())
+        }
+    }
+
+    impl<F: FieldExt> Blake2fTestCircuit<F> {
+        /// Returns the instance columns binding the circuit to its inputs
+        /// and expected outputs.
+        pub fn instance(&self) -> Vec<Vec<F>> {
+            vec![public_inputs(&self.inputs, &self.outputs, MAX_CALLS)]
+        }
+    }
+
+    /// `Blake2fTestCircuit` with its byte-aligned rotations laid out on rows
+    /// of their own, to compare the two layouts.
+    #[derive(Default)]
+    pub struct Blake2fLaidOutRotationsCircuit<F>(pub Blake2fTestCircuit<F>);
+
+    impl<F: FieldExt> Circuit<F> for Blake2fLaidOutRotationsCircuit<F> {
+        type Config = Blake2fConfig<F>;
+        type FloorPlanner = SimpleFloorPlanner;
+
+        fn without_witnesses(&self) -> Self {
+            Self::default()
+        }
+
+        fn configure(meta: &mut halo2_proofs::plonk::ConstraintSystem<F>) -> Self::Config {
+            <Blake2fTestCircuit<F> as Circuit<F>>::configure(meta).with_byte_rotations_laid_out()
+        }
+
+        fn synthesize(&self, config: Self::Config, layouter: impl Layouter<F>) -> Result<(), Error> {
+            self.0.synthesize(config, layouter)
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use halo2_proofs::{dev::MockProver, halo2curves::bn256::Fr};
+    use ethers_core::types::H512;
+    use halo2_proofs::{
+        circuit::{Layouter, SimpleFloorPlanner, Value},
+        dev::MockProver,
+        halo2curves::bn256::Fr,
+        plonk::{Advice, Circuit, Column, ConstraintSystem, Error, FirstPhase, SecondPhase, Selector},
+        poly::Rotation,
+    };
     use std::marker::PhantomData;
 
-    use super::dev::{Blake2fTestCircuit, INPUTS_OUTPUTS};
+    use super::dev::{
+        Blake2fLaidOutRotationsCircuit, Blake2fTestCircuit, EIP152_VECTORS, INPUTS_OUTPUTS, MAX_CALLS,
+    };
+    use super::{
+        public_inputs, Blake2fCall, Blake2fChip, Blake2fConfig, Blake2fInstructions, Blake2fTable,
+        Blake2fWitness, BlockWord, Eip152Error, EIP152_INPUT_LENGTH, ROUNDS,
+    };
+    use crate::compression::compress_native;
+    use crate::utils::rlc;
+
+    fn run(circuit: &Blake2fTestCircuit<Fr>, instance: Vec<Vec<Fr>>) -> MockProver<Fr> {
+        let k = 12;
+        MockProver::run(k, circuit, instance).unwrap()
+    }
+
+    #[test]
+    fn test_eip152_input_round_trip() {
+        let (inputs, _) = INPUTS_OUTPUTS.clone();
+        let input = inputs[0].to_eip152_input();
+        assert_eq!(input.len(), EIP152_INPUT_LENGTH);
+        assert_eq!(&input[..4], &[0, 0, 0, 12]);
+        assert_eq!(input[EIP152_INPUT_LENGTH - 1], 1);
+
+        let witness = Blake2fWitness::from_eip152_input(&input).unwrap();
+        assert_eq!(witness.rounds, 12);
+        assert_eq!(witness.h[0], 0x6a09e667f2bdc948);
+        assert_eq!(witness.m[0], 0x636261);
+        assert_eq!(witness.t, [3, 0]);
+        assert!(witness.f);
+        assert_eq!(witness.to_eip152_input(), input);
+    }
+
+    #[test]
+    fn test_eip152_input_malformed() {
+        let (inputs, _) = INPUTS_OUTPUTS.clone();
+        let mut input = inputs[0].to_eip152_input();
+
+        assert_eq!(
+            Blake2fWitness::from_eip152_input(&[]),
+            Err(Eip152Error::InvalidLength(0))
+        );
+        assert_eq!(
+            Blake2fWitness::from_eip152_input(&input[1..]),
+            Err(Eip152Error::InvalidLength(212))
+        );
+        input[EIP152_INPUT_LENGTH - 1] = 2;
+        assert_eq!(
+            Blake2fWitness::from_eip152_input(&input),
+            Err(Eip152Error::InvalidFinalBlockFlag(2))
+        );
+    }
+
+    #[test]
+    fn test_eip152_vectors_native() {
+        // Vector 8 runs 2^32 - 1 rounds, see test_eip152_vector_8_native
+        for (idx, vector) in EIP152_VECTORS.iter().enumerate().take(8) {
+            match (Blake2fWitness::from_eip152_input(&vector.input), &vector.output) {
+                (Ok(witness), Ok(output)) => assert_eq!(witness.compress(), *output, "vector {}", idx),
+                (Err(err), Err(expected)) => assert_eq!(err, *expected, "vector {}", idx),
+                (witness, _) => panic!("vector {} decoded to {:?}", idx, witness),
+            }
+        }
+    }
+
+    #[test]
+    fn test_eip152_gas() {
+        for (idx, gas) in [(4, 0), (5, 12), (7, 1), (8, 0xffffffff)] {
+            let witness = Blake2fWitness::from_eip152_input(&EIP152_VECTORS[idx].input).unwrap();
+            assert_eq!(witness.gas(), gas, "vector {}", idx);
+        }
+    }
+
+    #[test]
+    #[ignore = "runs 2^32 - 1 rounds"]
+    fn test_eip152_vector_8_native() {
+        let vector = &EIP152_VECTORS[8];
+        let witness = Blake2fWitness::from_eip152_input(&vector.input).unwrap();
+        assert_eq!(Ok(witness.compress()), vector.output);
+    }
+
+    #[test]
+    fn test_eip152_vectors_circuit() {
+        // The malformed vectors 0 to 3 are in the table as failed calls, and
+        // the others as successful ones with the output of the test case
+        for (idx, vector) in EIP152_VECTORS.iter().enumerate().take(8) {
+            let mut circuit = LookupCircuit::new(vec![Blake2fCall::from_calldata(&vector.input)], 1);
+            assert_eq!(circuit.success, vector.output.is_ok(), "vector {}", idx);
+            if let Ok(output) = vector.output {
+                circuit.output = output;
+            }
+            let prover = run_lookup(&circuit);
+            assert_eq!(prover.verify(), Ok(()), "vector {}", idx);
+
+            // and not with the other success bit
+            circuit.success = !circuit.success;
+            let prover = run_lookup(&circuit);
+            assert!(prover.verify().is_err(), "vector {}", idx);
+        }
+    }
+
+    #[test]
+    fn test_eip152_vector_8_circuit() {
+        // 2^32 - 1 rounds take more round slots than any circuit has rows
+        // for, so synthesis rejects the call for want of them, while the
+        // same input in 12 rounds is proven
+        let vector = &EIP152_VECTORS[8];
+        let witness = Blake2fWitness::from_eip152_input(&vector.input).unwrap();
+        assert_eq!(witness.rounds, u32::MAX);
+        let output = vector.output.unwrap();
+        let circuit = LookupCircuit {
+            calls: vec![witness.clone().into()],
+            outputs: vec![output],
+            id: 1,
+            calldata: vector.input.clone(),
+            gas: witness.gas(),
+            output,
+            success: true,
+        };
+        let result = MockProver::run(12, &circuit, circuit.instance());
+        assert!(matches!(result, Err(Error::Synthesis)));
+
+        let witness = Blake2fWitness {
+            rounds: ROUNDS as u32,
+            ..witness
+        };
+        let circuit = LookupCircuit::new(vec![witness.into()], 1);
+        let prover = run_lookup(&circuit);
+        assert_eq!(prover.verify(), Ok(()));
+    }
 
     #[test]
     fn test_blake2f_circuit() {
@@ -283,8 +1116,521 @@ mod tests {
             _marker: PhantomData,
         };
 
-        let k = 8;
-        let prover = MockProver::run(k, &circuit, vec![]).unwrap();
+        let prover = run(&circuit, circuit.instance());
+        assert_eq!(prover.verify(), Ok(()));
+    }
+
+    #[test]
+    fn test_blake2f_circuit_wrong_output() {
+        let (inputs, mut outputs) = INPUTS_OUTPUTS.clone();
+        outputs[0].0[0] ^= 1;
+
+        let circuit: Blake2fTestCircuit<Fr> = Blake2fTestCircuit {
+            inputs,
+            outputs,
+            _marker: PhantomData,
+        };
+
+        let prover = run(&circuit, circuit.instance());
+        assert!(prover.verify().is_err());
+    }
+
+    #[test]
+    fn test_blake2f_circuit_wrong_final_block() {
+        // The output of the final block claimed for a block that is not
+        let (mut inputs, outputs) = INPUTS_OUTPUTS.clone();
+        inputs[0].f = false;
+
+        let circuit: Blake2fTestCircuit<Fr> = Blake2fTestCircuit {
+            inputs,
+            outputs,
+            _marker: PhantomData,
+        };
+
+        let prover = run(&circuit, circuit.instance());
+        assert!(prover.verify().is_err());
+    }
+
+    #[test]
+    fn test_blake2f_circuit_wrong_rounds() {
+        // The output of 12 rounds claimed for 11 of them
+        let (inputs, outputs) = INPUTS_OUTPUTS.clone();
+
+        let circuit: Blake2fTestCircuit<Fr> = Blake2fTestCircuit {
+            inputs,
+            outputs,
+            _marker: PhantomData,
+        };
+
+        let mut instance = circuit.instance();
+        instance[0][1] = Fr::from(11);
+        let prover = run(&circuit, instance);
+        assert!(prover.verify().is_err());
+    }
+
+    #[test]
+    fn test_blake2f_circuit_wrong_public_message() {
+        // A public message word other than the one compressed
+        let (inputs, outputs) = INPUTS_OUTPUTS.clone();
+
+        let circuit: Blake2fTestCircuit<Fr> = Blake2fTestCircuit {
+            inputs,
+            outputs,
+            _marker: PhantomData,
+        };
+
+        let mut instance = circuit.instance();
+        instance[0][10] += Fr::from(1);
+        let prover = run(&circuit, instance);
+        assert!(prover.verify().is_err());
+    }
+
+    #[test]
+    fn test_blake2f_circuit_padding() {
+        // No calls at all, every slot is padding
+        let circuit: Blake2fTestCircuit<Fr> = Blake2fTestCircuit::default();
+        assert_eq!(circuit.instance()[0][0], Fr::from(0));
+
+        let prover = run(&circuit, circuit.instance());
         assert_eq!(prover.verify(), Ok(()));
     }
+
+    // Two calls on the block of INPUTS_OUTPUTS, with the given rounds
+    fn shared_rounds_circuit(rounds: [u32; 2]) -> Blake2fTestCircuit<Fr> {
+        let (inputs, _) = INPUTS_OUTPUTS.clone();
+        let inputs = rounds
+            .iter()
+            .map(|rounds| Blake2fWitness {
+                rounds: *rounds,
+                ..inputs[0].clone()
+            })
+            .collect::<Vec<_>>();
+        let outputs = inputs.iter().map(Blake2fWitness::compress).collect();
+
+        Blake2fTestCircuit {
+            inputs,
+            outputs,
+            _marker: PhantomData,
+        }
+    }
+
+    #[test]
+    fn test_blake2f_circuit_shared_rounds() {
+        for rounds in [[5, 7], [12, 0], [0, 12], [1, 1], [0, 0]] {
+            let circuit = shared_rounds_circuit(rounds);
+            let prover = run(&circuit, circuit.instance());
+            assert_eq!(prover.verify(), Ok(()), "rounds {:?}", rounds);
+        }
+    }
+
+    #[test]
+    fn test_blake2f_circuit_rounds_over_budget() {
+        let circuit = shared_rounds_circuit([6, 7]);
+        assert!(MockProver::run(12, &circuit, circuit.instance()).is_err());
+    }
+
+    #[test]
+    fn test_blake2f_circuit_shared_rounds_swapped() {
+        // The output of each call claimed for the other one
+        let mut circuit = shared_rounds_circuit([5, 7]);
+        circuit.outputs.swap(0, 1);
+
+        let prover = run(&circuit, circuit.instance());
+        assert!(prover.verify().is_err());
+    }
+
+    #[test]
+    fn test_blake2f_circuit_byte_rotations_laid_out() {
+        // The same compressions with the rotations taking rows of their own
+        let (inputs, outputs) = INPUTS_OUTPUTS.clone();
+        let circuit = Blake2fLaidOutRotationsCircuit(Blake2fTestCircuit {
+            inputs,
+            outputs,
+            _marker: PhantomData,
+        });
+        let prover = MockProver::run(13, &circuit, circuit.0.instance()).unwrap();
+        assert_eq!(prover.verify(), Ok(()));
+    }
+
+    #[test]
+    #[should_panic(expected = "at least one call")]
+    fn test_blake2f_config_no_calls() {
+        let mut meta = ConstraintSystem::<Fr>::default();
+        let challenge = meta.challenge_usable_after(FirstPhase);
+        let table = Blake2fTable::construct(&mut meta, challenge);
+        Blake2fConfig::configure_with_public_io(&mut meta, table, ROUNDS, 0);
+    }
+
+    #[test]
+    fn test_blake2f_circuit_too_many_calls() {
+        let (inputs, outputs) = INPUTS_OUTPUTS.clone();
+
+        let circuit: Blake2fTestCircuit<Fr> = Blake2fTestCircuit {
+            inputs,
+            outputs,
+            _marker: PhantomData,
+        };
+
+        let mut instance = circuit.instance();
+        instance[0][0] = Fr::from(MAX_CALLS as u64 + 1);
+        let prover = run(&circuit, instance);
+        assert!(prover.verify().is_err());
+    }
+
+    // Compresses `blocks` one after the other, the first one from its h and
+    // every other one from the state the previous one ended with. The rounds
+    // of every block are public, followed by the final state.
+    #[derive(Default)]
+    struct ChainCircuit {
+        blocks: Vec<Blake2fWitness>,
+    }
+
+    // The number of rounds laid out by every compression of the chain
+    const CHAIN_ROUNDS: usize = 2;
+
+    impl ChainCircuit {
+        fn new() -> Self {
+            let (inputs, _) = INPUTS_OUTPUTS.clone();
+            let first = Blake2fWitness {
+                rounds: CHAIN_ROUNDS as u32,
+                t: [128, 0],
+                f: false,
+                ..inputs[0].clone()
+            };
+            let mut m = first.m;
+            m.reverse();
+            let second = Blake2fWitness {
+                h: [0; 8],
+                m,
+                t: [256, 0],
+                f: true,
+                ..first.clone()
+            };
+            Self {
+                blocks: vec![first, second],
+            }
+        }
+
+        fn instance(&self) -> Vec<Vec<Fr>> {
+            let mut h = self.blocks[0].h;
+            for block in self.blocks.iter() {
+                h = compress_native(block.rounds, h, block.m, block.t, block.f);
+            }
+            let rounds = self.blocks.iter().map(|block| Fr::from(block.rounds as u64));
+            vec![rounds.chain(h.map(Fr::from)).collect()]
+        }
+    }
+
+    impl Circuit<Fr> for ChainCircuit {
+        type Config = Blake2fConfig<Fr>;
+        type FloorPlanner = SimpleFloorPlanner;
+
+        fn without_witnesses(&self) -> Self {
+            Self {
+                blocks: self.blocks.clone(),
+            }
+        }
+
+        fn configure(meta: &mut ConstraintSystem<Fr>) -> Self::Config {
+            let challenge = meta.challenge_usable_after(FirstPhase);
+            let table = Blake2fTable::construct(meta, challenge);
+            Blake2fConfig::configure(meta, table, CHAIN_ROUNDS, 1)
+        }
+
+        fn synthesize(&self, config: Self::Config, mut layouter: impl Layouter<Fr>) -> Result<(), Error> {
+            let chip = Blake2fChip::construct(config.clone(), vec![]);
+            chip.load(&mut layouter)?;
+
+            let mut state = config
+                .compression
+                .assign_state(&mut layouter, self.blocks[0].h.map(Value::known))?;
+            for (idx, block) in self.blocks.iter().enumerate() {
+                if idx > 0 {
+                    state = chip.initialization(&mut layouter, &state)?;
+                }
+                let rounds = chip.assign_rounds(&mut layouter, idx)?;
+                state = chip.compress(
+                    &mut layouter,
+                    &state,
+                    &rounds,
+                    block.m.map(|word| BlockWord(Value::known(word))),
+                    block.t.map(|word| BlockWord(Value::known(word))),
+                    Value::known(block.f),
+                )?;
+            }
+
+            for (offset, word) in state.words()?.iter().enumerate() {
+                let cell = word.cell().ok_or(Error::Synthesis)?;
+                layouter.constrain_instance(cell.cell(), config.instance, self.blocks.len() + offset)?;
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_blake2f_chained_compressions() {
+        let circuit = ChainCircuit::new();
+        let prover = MockProver::run(12, &circuit, circuit.instance()).unwrap();
+        assert_eq!(prover.verify(), Ok(()));
+    }
+
+    #[test]
+    fn test_blake2f_chained_compressions_wrong_output() {
+        // The final state of the second block compressed from its own h
+        // instead of the state the first one ended with
+        let circuit = ChainCircuit::new();
+        let unchained = ChainCircuit {
+            blocks: circuit.blocks[1..].to_vec(),
+        };
+        let mut instance = circuit.instance();
+        instance[0].truncate(circuit.blocks.len());
+        instance[0].extend_from_slice(&unchained.instance()[0][1..]);
+
+        let prover = MockProver::run(12, &circuit, instance).unwrap();
+        assert!(prover.verify().is_err());
+    }
+
+    // A caller of the precompile looking up the call `id` with `calldata`,
+    // with the rounds, gas, output and success it expects, into the table of
+    // the proven ones
+    #[derive(Default)]
+    struct LookupCircuit {
+        calls: Vec<Blake2fCall>,
+        outputs: Vec<H512>,
+        id: usize,
+        calldata: Vec<u8>,
+        gas: u64,
+        output: H512,
+        success: bool,
+    }
+
+    #[derive(Clone)]
+    struct LookupConfig {
+        blake2f: Blake2fConfig<Fr>,
+        call: [Column<Advice>; 9],
+        q_call: Selector,
+    }
+
+    impl LookupCircuit {
+        fn new(calls: Vec<Blake2fCall>, id: usize) -> Self {
+            let outputs = calls.iter().map(Blake2fCall::output).collect::<Vec<_>>();
+            let call = &calls[id - 1];
+            Self {
+                calldata: call.calldata.clone(),
+                gas: call.witness.gas(),
+                output: outputs[id - 1],
+                success: call.success(),
+                calls,
+                outputs,
+                id,
+            }
+        }
+
+        fn instance(&self) -> Vec<Vec<Fr>> {
+            let inputs = self.calls.iter().map(|call| call.witness.clone()).collect::<Vec<_>>();
+            vec![public_inputs(&inputs, &self.outputs, MAX_CALLS)]
+        }
+    }
+
+    impl Circuit<Fr> for LookupCircuit {
+        type Config = LookupConfig;
+        type FloorPlanner = SimpleFloorPlanner;
+
+        fn without_witnesses(&self) -> Self {
+            Self::default()
+        }
+
+        fn configure(meta: &mut ConstraintSystem<Fr>) -> Self::Config {
+            let challenge = meta.challenge_usable_after(FirstPhase);
+            let table = Blake2fTable::construct(meta, challenge);
+            let blake2f = Blake2fConfig::configure_with_public_io(meta, table, ROUNDS, MAX_CALLS);
+            let call = [
+                meta.advice_column(),
+                meta.advice_column(),
+                meta.advice_column(),
+                meta.advice_column(),
+                meta.advice_column_in(SecondPhase),
+                meta.advice_column_in(SecondPhase),
+                meta.advice_column(),
+                meta.advice_column(),
+                meta.advice_column(),
+            ];
+            let q_call = meta.complex_selector();
+
+            meta.lookup_any("blake2f call", |meta| {
+                let q_call = meta.query_selector(q_call);
+                call.iter()
+                    .map(|column| q_call.clone() * meta.query_advice(*column, Rotation::cur()))
+                    .zip(table.table_exprs(meta))
+                    .collect()
+            });
+
+            LookupConfig { blake2f, call, q_call }
+        }
+
+        fn synthesize(&self, config: Self::Config, mut layouter: impl Layouter<Fr>) -> Result<(), Error> {
+            let table = config.blake2f.table;
+            let chip = Blake2fChip::construct_with_calls(config.blake2f, self.calls.clone());
+            chip.load(&mut layouter)?;
+            chip.assign(&mut layouter)?;
+
+            // The rounds and flag byte of calldata of the wrong length are 0,
+            // and so are the output and final block flag of a failed call
+            let r = layouter.get_challenge(table.challenge);
+            let expected = Blake2fCall::from_calldata(&self.calldata);
+            let values = [
+                Value::known(Fr::from(self.id as u64)),
+                Value::known(Fr::from(expected.witness.rounds as u64)),
+                Value::known(Fr::from(self.gas)),
+                Value::known(Fr::from(self.calldata.len() as u64)),
+                r.map(|r| rlc(&self.calldata, r)),
+                r.map(|r| if self.success { rlc(self.output.as_bytes(), r) } else { Fr::from(0) }),
+                Value::known(Fr::from((self.success && expected.witness.f) as u64)),
+                Value::known(Fr::from(expected.flag() as u64)),
+                Value::known(Fr::from(self.success as u64)),
+            ];
+            layouter.assign_region(
+                || "call",
+                |mut region| {
+                    config.q_call.enable(&mut region, 0)?;
+                    for (column, value) in config.call.iter().zip(values) {
+                        region.assign_advice(|| "call", *column, 0, || value)?;
+                    }
+                    Ok(())
+                },
+            )
+        }
+    }
+
+    fn run_lookup(circuit: &LookupCircuit) -> MockProver<Fr> {
+        MockProver::run(12, circuit, circuit.instance()).unwrap()
+    }
+
+    #[test]
+    fn test_blake2f_table_lookup() {
+        let (inputs, _) = INPUTS_OUTPUTS.clone();
+        let circuit = LookupCircuit::new(vec![inputs[0].clone().into()], 1);
+
+        let prover = run_lookup(&circuit);
+        assert_eq!(prover.verify(), Ok(()));
+    }
+
+    #[test]
+    fn test_blake2f_table_lookup_wrong_output() {
+        // A call whose output differs from the proven one is not in the table
+        let (inputs, _) = INPUTS_OUTPUTS.clone();
+        let mut circuit = LookupCircuit::new(vec![inputs[0].clone().into()], 1);
+        circuit.output.0[63] ^= 1;
+
+        let prover = run_lookup(&circuit);
+        assert!(prover.verify().is_err());
+    }
+
+    #[test]
+    fn test_blake2f_table_lookup_wrong_gas() {
+        // The gas of a call is its number of rounds
+        let (inputs, _) = INPUTS_OUTPUTS.clone();
+        let mut circuit = LookupCircuit::new(vec![inputs[0].clone().into()], 1);
+        assert_eq!(circuit.gas, 12);
+        circuit.gas += 1;
+
+        let prover = run_lookup(&circuit);
+        assert!(prover.verify().is_err());
+    }
+
+    #[test]
+    fn test_blake2f_table_lookup_disabled_call() {
+        // A call in a slot the public number of calls leaves out has no id
+        let (inputs, _) = INPUTS_OUTPUTS.clone();
+        let circuit = LookupCircuit::new(vec![inputs[0].clone().into()], 1);
+
+        let mut instance = circuit.instance();
+        instance[0][0] = Fr::from(0);
+        let prover = MockProver::run(12, &circuit, instance).unwrap();
+        assert!(prover.verify().is_err());
+    }
+
+    #[test]
+    fn test_blake2f_failed_calls() {
+        let (inputs, _) = INPUTS_OUTPUTS.clone();
+        let input = inputs[0].to_eip152_input();
+        let mut bad_flag = input.clone();
+        bad_flag[EIP152_INPUT_LENGTH - 1] = 2;
+
+        for calldata in [&input[1..], &bad_flag[..], &[]] {
+            let circuit = LookupCircuit::new(vec![Blake2fCall::from_calldata(calldata)], 1);
+            assert!(!circuit.success);
+            let prover = run_lookup(&circuit);
+            assert_eq!(prover.verify(), Ok(()), "calldata of {} bytes", calldata.len());
+        }
+    }
+
+    #[test]
+    fn test_blake2f_failed_call_claimed_successful() {
+        let (inputs, _) = INPUTS_OUTPUTS.clone();
+        let calls = vec![Blake2fCall::from_calldata(&inputs[0].to_eip152_input()[1..])];
+        let mut circuit = LookupCircuit::new(calls, 1);
+        circuit.success = true;
+
+        let prover = run_lookup(&circuit);
+        assert!(prover.verify().is_err());
+    }
+
+    #[test]
+    fn test_blake2f_failed_call_applies_no_rounds() {
+        // A call with an invalid flag byte takes none of the round slots, so
+        // the 12 rounds of the chip are left to the valid call after it
+        let (inputs, _) = INPUTS_OUTPUTS.clone();
+        let mut bad_flag = inputs[0].to_eip152_input();
+        bad_flag[EIP152_INPUT_LENGTH - 1] = 2;
+        let calls = vec![Blake2fCall::from_calldata(&bad_flag), inputs[0].clone().into()];
+        assert_eq!(calls[0].witness.rounds, 12);
+
+        for id in [1, 2] {
+            let circuit = LookupCircuit::new(calls.clone(), id);
+            let prover = run_lookup(&circuit);
+            assert_eq!(prover.verify(), Ok(()), "call {}", id);
+        }
+    }
+
+    #[test]
+    fn test_blake2f_valid_call_claimed_failed() {
+        // The length and RLC of valid calldata are those of the input of its
+        // compression, flag byte last, so no call the prover proves it with
+        // is in the table as failed
+        let (inputs, _) = INPUTS_OUTPUTS.clone();
+        let input = inputs[0].to_eip152_input();
+        let mut bad_flag = input.clone();
+        bad_flag[EIP152_INPUT_LENGTH - 1] = 2;
+        let mut tampered: Blake2fCall = inputs[0].clone().into();
+        tampered.calldata[EIP152_INPUT_LENGTH - 1] = 2;
+
+        for call in [
+            Blake2fCall::from_calldata(&input),
+            Blake2fCall::from_calldata(&input[1..]),
+            Blake2fCall::from_calldata(&bad_flag),
+            tampered,
+        ] {
+            let mut circuit = LookupCircuit::new(vec![call], 1);
+            circuit.calldata = input.clone();
+            circuit.gas = inputs[0].gas();
+            circuit.success = false;
+
+            let prover = run_lookup(&circuit);
+            assert!(prover.verify().is_err());
+        }
+    }
+
+    #[test]
+    fn test_blake2f_call_wrong_flag() {
+        // A successful call whose flag byte is not the final block flag
+        // compressed with
+        let (inputs, _) = INPUTS_OUTPUTS.clone();
+        let mut call: Blake2fCall = inputs[0].clone().into();
+        call.calldata[EIP152_INPUT_LENGTH - 1] = 0;
+        let circuit = LookupCircuit::new(vec![call], 1);
+
+        let prover = run_lookup(&circuit);
+        assert!(prover.verify().is_err());
+    }
 }
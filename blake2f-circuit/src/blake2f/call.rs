@@ -0,0 +1,239 @@
+use halo2_proofs::{
+    arithmetic::FieldExt,
+    circuit::{AssignedCell, Layouter, Value},
+    plonk::{Advice, Challenge, Column, ConstraintSystem, Error, Expression, SecondPhase, Selector},
+    poly::Rotation,
+};
+
+use super::EIP152_INPUT_LENGTH;
+
+/// Decides whether a call to the precompile succeeds, from the length of its
+/// calldata and its final block flag byte, binds both to the RLC of the
+/// calldata and gives the call its id, on five rows:
+///
+/// | value   | inv      | is_zero | rlc       |        |
+/// |---------|----------|---------|-----------|--------|
+/// | length  | inv_len  | is_len  | acc       | s_call |
+/// | flag    | inv_flag | is_flag | input_rlc |        |
+/// | f       | rounds   | success |           |        |
+/// | enabled | slot     | id      |           |        |
+/// | applied |          |         |           |        |
+///
+/// is_len is 1 exactly when the length is `EIP152_INPUT_LENGTH`, is_flag
+/// exactly when the flag byte is 0 or 1, and success is their product. acc
+/// is the RLC of the first 212 bytes of the input of the compression, which
+/// is the whole calldata but its flag byte when the length is right, so the
+/// RLC of the calldata is then acc * r + flag. Otherwise, the RLC is that of
+/// calldata the compression does not read, and the flag byte and the number
+/// of rounds are 0. On success, the flag byte has to be the final block flag
+/// f of the compression, which is copied in, and f is 0 otherwise.
+///
+/// A failed call applies none of its rounds, so the number of rounds applied
+/// is the number of rounds times success. The id is the fixed slot of the
+/// call if it is enabled, and 0 for padding.
+///
+/// The RLC of the output of a call, once compressed, is also 0 unless it
+/// succeeds, on two rows:
+///
+/// | value   | rlc        |          |
+/// |---------|------------|----------|
+/// | success | rlc        | s_output |
+/// |         | output_rlc |          |
+#[derive(Clone, Copy, Debug)]
+pub struct CallConfig {
+    value: Column<Advice>,
+    inv: Column<Advice>,
+    is_zero: Column<Advice>,
+    rlc: Column<Advice>,
+    s_call: Selector,
+    s_output: Selector,
+}
+
+/// The cells of a call the chip puts on its row of the table, and the
+/// number of rounds its compression applies.
+#[derive(Clone, Debug)]
+pub struct AssignedCall<F: FieldExt> {
+    pub id: AssignedCell<F, F>,
+    pub length: AssignedCell<F, F>,
+    pub flag: AssignedCell<F, F>,
+    pub input_rlc: AssignedCell<F, F>,
+    pub success: AssignedCell<F, F>,
+    pub applied_rounds: AssignedCell<F, F>,
+}
+
+impl CallConfig {
+    pub fn configure<F: FieldExt>(meta: &mut ConstraintSystem<F>, challenge: Challenge) -> Self {
+        let value = meta.advice_column();
+        let inv = meta.advice_column();
+        let is_zero = meta.advice_column();
+        let rlc = meta.advice_column_in(SecondPhase);
+        meta.enable_equality(value);
+        meta.enable_equality(inv);
+        meta.enable_equality(is_zero);
+        meta.enable_equality(rlc);
+        let s_call = meta.selector();
+        let s_output = meta.selector();
+
+        meta.create_gate("call", |meta| {
+            let s_call = meta.query_selector(s_call);
+            let one = Expression::Constant(F::one());
+            let r = meta.query_challenge(challenge);
+            let length = meta.query_advice(value, Rotation::cur());
+            let flag = meta.query_advice(value, Rotation::next());
+            let f = meta.query_advice(value, Rotation(2));
+            let inv_len = meta.query_advice(inv, Rotation::cur());
+            let inv_flag = meta.query_advice(inv, Rotation::next());
+            let rounds = meta.query_advice(inv, Rotation(2));
+            let is_len = meta.query_advice(is_zero, Rotation::cur());
+            let is_flag = meta.query_advice(is_zero, Rotation::next());
+            let success = meta.query_advice(is_zero, Rotation(2));
+            let enabled = meta.query_advice(value, Rotation(3));
+            let slot = meta.query_advice(inv, Rotation(3));
+            let id = meta.query_advice(is_zero, Rotation(3));
+            let applied = meta.query_advice(value, Rotation(4));
+            let acc = meta.query_advice(rlc, Rotation::cur());
+            let input_rlc = meta.query_advice(rlc, Rotation::next());
+
+            let len_diff = length - Expression::Constant(F::from(EIP152_INPUT_LENGTH as u64));
+            let flag_range = flag.clone() * (flag.clone() - one.clone());
+            let not_len = one.clone() - is_len.clone();
+            vec![
+                s_call.clone() * (one.clone() - len_diff.clone() * inv_len - is_len.clone()),
+                s_call.clone() * len_diff * is_len.clone(),
+                s_call.clone() * (one.clone() - flag_range.clone() * inv_flag - is_flag.clone()),
+                s_call.clone() * flag_range * is_flag.clone(),
+                s_call.clone() * (is_len.clone() * is_flag - success.clone()),
+                s_call.clone() * is_len * (acc * r + flag.clone() - input_rlc),
+                s_call.clone() * not_len.clone() * flag.clone(),
+                s_call.clone() * not_len * rounds.clone(),
+                s_call.clone() * success.clone() * (f.clone() - flag),
+                s_call.clone() * (one - success.clone()) * f,
+                s_call.clone() * (success * rounds - applied),
+                s_call * (enabled * slot - id),
+            ]
+        });
+
+        meta.create_gate("call output", |meta| {
+            let s_output = meta.query_selector(s_output);
+            let success = meta.query_advice(value, Rotation::cur());
+            let rlc_out = meta.query_advice(rlc, Rotation::cur());
+            let output_rlc = meta.query_advice(rlc, Rotation::next());
+            vec![s_output * (success * rlc_out - output_rlc)]
+        });
+
+        Self {
+            value,
+            inv,
+            is_zero,
+            rlc,
+            s_call,
+            s_output,
+        }
+    }
+
+    /// Assigns the call in `slot`, counting from 1, with `length` bytes of
+    /// calldata of RLC `input_rlc` and final block flag byte `flag`, which is
+    /// 0 unless the length is right. The call is compressed in `rounds`
+    /// rounds with final block flag `f`, from an input whose first 212 bytes
+    /// have the RLC `acc`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn assign<F: FieldExt>(
+        &self,
+        layouter: &mut impl Layouter<F>,
+        slot: usize,
+        enabled: &AssignedCell<F, F>,
+        length: Value<u64>,
+        flag: Value<u8>,
+        input_rlc: Value<F>,
+        rounds: &AssignedCell<F, F>,
+        f: &AssignedCell<F, F>,
+        acc: &AssignedCell<F, F>,
+    ) -> Result<AssignedCall<F>, Error> {
+        layouter.assign_region(
+            || "call",
+            |mut region| {
+                self.s_call.enable(&mut region, 0)?;
+
+                let length = length.map(F::from);
+                let flag = flag.map(|flag| F::from(flag as u64));
+                let len_diff = length - Value::known(F::from(EIP152_INPUT_LENGTH as u64));
+                let flag_range = flag * (flag - Value::known(F::one()));
+                let is_len = len_diff.map(|diff| F::from(diff.is_zero_vartime() as u64));
+                let is_flag = flag_range.map(|range| F::from(range.is_zero_vartime() as u64));
+
+                let mut cells = Vec::with_capacity(2);
+                for (offset, (value, diff, is_zero)) in
+                    [(length, len_diff, is_len), (flag, flag_range, is_flag)].into_iter().enumerate()
+                {
+                    cells.push(region.assign_advice(|| "value", self.value, offset, || value)?);
+                    region.assign_advice(
+                        || "inv",
+                        self.inv,
+                        offset,
+                        || diff.map(|diff| diff.invert().unwrap_or(F::zero())),
+                    )?;
+                    region.assign_advice(|| "is_zero", self.is_zero, offset, || is_zero)?;
+                }
+                let (length, flag) = (cells[0].clone(), cells[1].clone());
+
+                acc.copy_advice(|| "acc", &mut region, self.rlc, 0)?;
+                let input_rlc = region.assign_advice(|| "input_rlc", self.rlc, 1, || input_rlc)?;
+
+                f.copy_advice(|| "f", &mut region, self.value, 2)?;
+                rounds.copy_advice(|| "rounds", &mut region, self.inv, 2)?;
+                let success = region.assign_advice(|| "success", self.is_zero, 2, || is_len * is_flag)?;
+
+                let slot = F::from(slot as u64);
+                enabled.copy_advice(|| "enabled", &mut region, self.value, 3)?;
+                let slot_cell = region.assign_advice(|| "slot", self.inv, 3, || Value::known(slot))?;
+                region.constrain_constant(slot_cell.cell(), slot)?;
+                let id = region.assign_advice(
+                    || "id",
+                    self.is_zero,
+                    3,
+                    || enabled.value().map(|enabled| *enabled * slot),
+                )?;
+
+                let applied_rounds = region.assign_advice(
+                    || "applied",
+                    self.value,
+                    4,
+                    || success.value().copied() * rounds.value().copied(),
+                )?;
+
+                Ok(AssignedCall {
+                    id,
+                    length,
+                    flag,
+                    input_rlc,
+                    success,
+                    applied_rounds,
+                })
+            },
+        )
+    }
+
+    /// Returns the RLC of the output of a call, which is `rlc` if it
+    /// succeeds and 0 otherwise.
+    pub fn assign_output<F: FieldExt>(
+        &self,
+        layouter: &mut impl Layouter<F>,
+        success: &AssignedCell<F, F>,
+        rlc: &AssignedCell<F, F>,
+    ) -> Result<AssignedCell<F, F>, Error> {
+        layouter.assign_region(
+            || "call output",
+            |mut region| {
+                self.s_output.enable(&mut region, 0)?;
+                success.copy_advice(|| "success", &mut region, self.value, 0)?;
+                rlc.copy_advice(|| "rlc", &mut region, self.rlc, 0)?;
+                region.assign_advice(
+                    || "output_rlc",
+                    self.rlc,
+                    1,
+                    || success.value().copied() * rlc.value().copied(),
+                )
+            },
+        )
+    }
+}
@@ -0,0 +1,327 @@
+use halo2_proofs::{
+    arithmetic::FieldExt,
+    circuit::{AssignedCell, Layouter, Value},
+    plonk::{Advice, Column, ConstraintSystem, Error, Expression, Fixed, SecondPhase, Selector},
+    poly::Rotation,
+};
+
+// The number of distinct message schedules, after which they repeat
+const PHASES: u64 = 10;
+
+/// Shares a budget of round slots among the compressions of a chip, each of
+/// them taking as many consecutive slots as it has rounds. A slot lays out a
+/// single round, and is summed up on a row of its own by the RLCs of the work
+/// vector it starts from, of the one it ends with and of its message words:
+///
+/// | idx   | first | start | phase | wrap | rlc_in | rlc_out | rlc_msg |        |
+/// |-------|-------|-------|-------|------|--------|---------|---------|--------|
+/// | 0     | 0     | 0     | 9     | 0    | 0      | 0       | 0       |        |
+/// | 1     | 1     | 1     | 0     | 0    | ...    | ...     | ...     | s_slot |
+/// | 2     | 0     | 1     | 1     | 0    | ...    | ...     | ...     | s_slot |
+/// | ...   | ...   | ...   | ...   | ...  | ...    | ...     | ...     | s_slot |
+///
+/// A slot that is `first` of its compression starts from a fresh work
+/// vector, and any other one from the output of the slot before, which the
+/// round layout selects by the flag. `start` is the index of the last first
+/// slot, and `phase` counts the rounds since then modulo 10, wrapping around
+/// when `wrap` is set. The message words of a slot are looked up by `start`
+/// and `phase` among the message schedules of the compressions.
+///
+/// Every compression has a row of its own, on which its start is the end of
+/// the compression before, and from which its first and last slots are
+/// looked up when it has any round:
+///
+/// | rounds | inv   | applied | call_start | key   | rlc_init | rlc_final |        |
+/// |--------|-------|---------|------------|-------|----------|-----------|--------|
+/// | 12     | 1/12  | 1       | 1          | 1     | ...      | ...       | s_call |
+/// | 0      | 0     | 0       | 13         | 0     | ...      | ...       | s_call |
+/// |        |       |         | 13         |       |          |           |        |
+///
+/// The first slot of a compression is `call_start`, where it starts from the
+/// work vector `rlc_init` is taken of. Its last slot still has the same
+/// start, so no other compression started in between, and ends with the work
+/// vector `rlc_final` is taken of. `key` is the start of a compression with
+/// any round and 0 otherwise, and labels its message schedules, ten rows of
+/// (key, phase, rlc) after a row of zeros.
+#[derive(Clone, Copy, Debug)]
+pub struct RoundSlotsConfig {
+    idx: Column<Fixed>,
+    first: Column<Advice>,
+    start: Column<Advice>,
+    phase: Column<Advice>,
+    wrap: Column<Advice>,
+    rlc_in: Column<Advice>,
+    rlc_out: Column<Advice>,
+    rlc_msg: Column<Advice>,
+    s_slot: Selector,
+    rounds: Column<Advice>,
+    inv: Column<Advice>,
+    applied: Column<Advice>,
+    call_start: Column<Advice>,
+    key: Column<Advice>,
+    rlc_init: Column<Advice>,
+    rlc_final: Column<Advice>,
+    s_call: Selector,
+    schedule_key: Column<Advice>,
+    schedule_phase: Column<Advice>,
+    schedule_rlc: Column<Advice>,
+}
+
+/// The cells a round slot is summed up by.
+#[derive(Clone, Debug)]
+pub struct RoundSlotRow<F: FieldExt> {
+    pub first: AssignedCell<F, F>,
+    pub rlc_in: AssignedCell<F, F>,
+    pub rlc_out: AssignedCell<F, F>,
+    pub rlc_msg: AssignedCell<F, F>,
+}
+
+/// The cells a compression is summed up by: the number of rounds it applies,
+/// none for a failed call, the RLCs of its work vector before and after its
+/// rounds, and the RLCs of the ten message schedules of its block.
+#[derive(Clone, Debug)]
+pub struct RoundSlotsCall<F: FieldExt> {
+    pub rounds: AssignedCell<F, F>,
+    pub rlc_init: AssignedCell<F, F>,
+    pub rlc_final: AssignedCell<F, F>,
+    pub schedules: Vec<AssignedCell<F, F>>,
+}
+
+impl RoundSlotsConfig {
+    pub fn configure<F: FieldExt>(meta: &mut ConstraintSystem<F>) -> Self {
+        let idx = meta.fixed_column();
+        let [first, start, phase, wrap] = [(); 4].map(|_| meta.advice_column());
+        let [rlc_in, rlc_out, rlc_msg] = [(); 3].map(|_| meta.advice_column_in(SecondPhase));
+        let [rounds, inv, applied, call_start, key] = [(); 5].map(|_| meta.advice_column());
+        let [rlc_init, rlc_final] = [(); 2].map(|_| meta.advice_column_in(SecondPhase));
+        let [schedule_key, schedule_phase] = [(); 2].map(|_| meta.advice_column());
+        let schedule_rlc = meta.advice_column_in(SecondPhase);
+        for column in [
+            first, start, phase, rlc_in, rlc_out, rlc_msg, rounds, call_start, key, rlc_init,
+            rlc_final, schedule_key, schedule_phase, schedule_rlc,
+        ] {
+            meta.enable_equality(column);
+        }
+        let s_slot = meta.complex_selector();
+        let s_call = meta.complex_selector();
+
+        meta.create_gate("round slot", |meta| {
+            let s_slot = meta.query_selector(s_slot);
+            let one = Expression::Constant(F::one());
+            let idx = meta.query_fixed(idx, Rotation::cur());
+            let first = meta.query_advice(first, Rotation::cur());
+            let start_prev = meta.query_advice(start, Rotation::prev());
+            let start = meta.query_advice(start, Rotation::cur());
+            let phase_prev = meta.query_advice(phase, Rotation::prev());
+            let phase = meta.query_advice(phase, Rotation::cur());
+            let wrap = meta.query_advice(wrap, Rotation::cur());
+
+            let next_phase = phase_prev + one.clone() - wrap.clone() * Expression::Constant(F::from(PHASES));
+            vec![
+                s_slot.clone() * first.clone() * (one.clone() - first.clone()),
+                s_slot.clone()
+                    * (first.clone() * idx + (one.clone() - first.clone()) * start_prev - start),
+                s_slot.clone() * wrap.clone() * (one.clone() - wrap),
+                s_slot * ((one - first) * next_phase - phase),
+            ]
+        });
+
+        // Also keeps the phase below 10, as no schedule has a higher one
+        meta.lookup_any("round slot message", |meta| {
+            let s_slot = meta.query_selector(s_slot);
+            [(start, schedule_key), (phase, schedule_phase), (rlc_msg, schedule_rlc)]
+                .into_iter()
+                .map(|(input, table)| {
+                    (
+                        s_slot.clone() * meta.query_advice(input, Rotation::cur()),
+                        meta.query_advice(table, Rotation::cur()),
+                    )
+                })
+                .collect()
+        });
+
+        meta.create_gate("round slots call", |meta| {
+            let s_call = meta.query_selector(s_call);
+            let one = Expression::Constant(F::one());
+            let rounds = meta.query_advice(rounds, Rotation::cur());
+            let inv = meta.query_advice(inv, Rotation::cur());
+            let applied = meta.query_advice(applied, Rotation::cur());
+            let start = meta.query_advice(call_start, Rotation::cur());
+            let start_next = meta.query_advice(call_start, Rotation::next());
+            let key = meta.query_advice(key, Rotation::cur());
+            vec![
+                s_call.clone() * (rounds.clone() * inv - applied.clone()),
+                s_call.clone() * rounds.clone() * (one - applied.clone()),
+                s_call.clone() * (applied * start.clone() - key),
+                s_call * (start + rounds - start_next),
+            ]
+        });
+
+        // The first slot of a compression with any round is its start, and
+        // starts from its initial work vector
+        meta.lookup_any("round slots call start", |meta| {
+            let q = meta.query_selector(s_call) * meta.query_advice(applied, Rotation::cur());
+            let call_start = meta.query_advice(call_start, Rotation::cur());
+            let rlc_init = meta.query_advice(rlc_init, Rotation::cur());
+            vec![
+                (q.clone() * call_start, meta.query_fixed(idx, Rotation::cur())),
+                (q.clone(), meta.query_advice(first, Rotation::cur())),
+                (q * rlc_init, meta.query_advice(rlc_in, Rotation::cur())),
+            ]
+        });
+
+        // Its last slot has not started another compression since, and ends
+        // with its final work vector
+        meta.lookup_any("round slots call end", |meta| {
+            let q = meta.query_selector(s_call) * meta.query_advice(applied, Rotation::cur());
+            let one = Expression::Constant(F::one());
+            let call_start = meta.query_advice(call_start, Rotation::cur());
+            let rounds = meta.query_advice(rounds, Rotation::cur());
+            let rlc_final = meta.query_advice(rlc_final, Rotation::cur());
+            vec![
+                (q.clone() * (call_start.clone() + rounds - one), meta.query_fixed(idx, Rotation::cur())),
+                (q.clone() * call_start, meta.query_advice(start, Rotation::cur())),
+                (q * rlc_final, meta.query_advice(rlc_out, Rotation::cur())),
+            ]
+        });
+
+        Self {
+            idx,
+            first,
+            start,
+            phase,
+            wrap,
+            rlc_in,
+            rlc_out,
+            rlc_msg,
+            s_slot,
+            rounds,
+            inv,
+            applied,
+            call_start,
+            key,
+            rlc_init,
+            rlc_final,
+            s_call,
+            schedule_key,
+            schedule_phase,
+            schedule_rlc,
+        }
+    }
+
+    /// Lays out the row of every compression, in order, and the table of
+    /// their message schedules. Returns, for each of them, whether its rounds
+    /// are applied, i.e. it has any.
+    pub fn assign_calls<F: FieldExt>(
+        &self,
+        layouter: &mut impl Layouter<F>,
+        calls: &[RoundSlotsCall<F>],
+    ) -> Result<Vec<AssignedCell<F, F>>, Error> {
+        let (applied, keys) = layouter.assign_region(
+            || "round slots calls",
+            |mut region| {
+                let mut applied = Vec::with_capacity(calls.len());
+                let mut keys = Vec::with_capacity(calls.len());
+                let mut start =
+                    region.assign_advice(|| "call_start", self.call_start, 0, || Value::known(F::one()))?;
+                region.constrain_constant(start.cell(), F::one())?;
+
+                for (offset, call) in calls.iter().enumerate() {
+                    self.s_call.enable(&mut region, offset)?;
+                    let rounds = call.rounds.copy_advice(|| "rounds", &mut region, self.rounds, offset)?;
+                    let rounds = rounds.value().copied();
+                    region.assign_advice(
+                        || "inv",
+                        self.inv,
+                        offset,
+                        || rounds.map(|rounds| rounds.invert().unwrap_or(F::zero())),
+                    )?;
+                    let is_applied = rounds.map(|rounds| F::from(!rounds.is_zero_vartime() as u64));
+                    applied.push(region.assign_advice(|| "applied", self.applied, offset, || is_applied)?);
+                    keys.push(region.assign_advice(
+                        || "key",
+                        self.key,
+                        offset,
+                        || is_applied * start.value().copied(),
+                    )?);
+                    call.rlc_init.copy_advice(|| "rlc_init", &mut region, self.rlc_init, offset)?;
+                    call.rlc_final.copy_advice(|| "rlc_final", &mut region, self.rlc_final, offset)?;
+
+                    let next = start.value().copied() + rounds;
+                    start = region.assign_advice(|| "call_start", self.call_start, offset + 1, || next)?;
+                }
+                Ok((applied, keys))
+            },
+        )?;
+
+        layouter.assign_region(
+            || "message schedules",
+            |mut region| {
+                for column in [self.schedule_key, self.schedule_phase, self.schedule_rlc] {
+                    region.assign_advice(|| "zero", column, 0, || Value::known(F::zero()))?;
+                }
+
+                let mut offset = 1;
+                for (call, key) in calls.iter().zip(keys.iter()) {
+                    for (phase, rlc) in call.schedules.iter().enumerate() {
+                        key.copy_advice(|| "key", &mut region, self.schedule_key, offset)?;
+                        let phase = F::from(phase as u64);
+                        let cell =
+                            region.assign_advice(|| "phase", self.schedule_phase, offset, || Value::known(phase))?;
+                        region.constrain_constant(cell.cell(), phase)?;
+                        rlc.copy_advice(|| "rlc", &mut region, self.schedule_rlc, offset)?;
+                        offset += 1;
+                    }
+                }
+                Ok(())
+            },
+        )?;
+
+        Ok(applied)
+    }
+
+    /// Lays out the row of every round slot, in order, after the row of
+    /// zeros the first slot follows.
+    pub fn assign_slots<F: FieldExt>(
+        &self,
+        layouter: &mut impl Layouter<F>,
+        slots: &[RoundSlotRow<F>],
+    ) -> Result<(), Error> {
+        layouter.assign_region(
+            || "round slots",
+            |mut region| {
+                region.assign_fixed(|| "idx", self.idx, 0, || Value::known(F::zero()))?;
+                for column in [self.first, self.wrap, self.rlc_in, self.rlc_out, self.rlc_msg] {
+                    region.assign_advice(|| "zero", column, 0, || Value::known(F::zero()))?;
+                }
+                let start = region.assign_advice(|| "start", self.start, 0, || Value::known(F::zero()))?;
+                region.constrain_constant(start.cell(), F::zero())?;
+                let last_phase = F::from(PHASES - 1);
+                let phase = region.assign_advice(|| "phase", self.phase, 0, || Value::known(last_phase))?;
+                region.constrain_constant(phase.cell(), last_phase)?;
+
+                let mut start = Value::known(0);
+                let mut phase = Value::known(PHASES - 1);
+                for (idx, slot) in slots.iter().enumerate() {
+                    let offset = idx + 1;
+                    self.s_slot.enable(&mut region, offset)?;
+                    region.assign_fixed(|| "idx", self.idx, offset, || Value::known(F::from(offset as u64)))?;
+                    let first = slot.first.copy_advice(|| "first", &mut region, self.first, offset)?;
+                    let first = first.value().map(|first| *first == F::one());
+
+                    let wrap = first.zip(phase).map(|(first, phase)| !first && phase == PHASES - 1);
+                    start = first.zip(start).map(|(first, start)| if first { offset as u64 } else { start });
+                    phase = first.zip(phase).map(|(first, phase)| if first { 0 } else { (phase + 1) % PHASES });
+                    region.assign_advice(|| "start", self.start, offset, || start.map(F::from))?;
+                    region.assign_advice(|| "phase", self.phase, offset, || phase.map(F::from))?;
+                    region.assign_advice(|| "wrap", self.wrap, offset, || wrap.map(|wrap| F::from(wrap as u64)))?;
+
+                    slot.rlc_in.copy_advice(|| "rlc_in", &mut region, self.rlc_in, offset)?;
+                    slot.rlc_out.copy_advice(|| "rlc_out", &mut region, self.rlc_out, offset)?;
+                    slot.rlc_msg.copy_advice(|| "rlc_msg", &mut region, self.rlc_msg, offset)?;
+                }
+                Ok(())
+            },
+        )
+    }
+}
@@ -0,0 +1,213 @@
+use halo2_proofs::{
+    arithmetic::FieldExt,
+    circuit::{AssignedCell, Layouter, Value},
+    plonk::{
+        Advice, Any, Challenge, Column, ConstraintSystem, Error, Expression, SecondPhase, Selector,
+        VirtualCells,
+    },
+    poly::Rotation,
+};
+
+/// The lookup table of the BLAKE2 F precompile calls proven by the chip.
+/// Row 0 is all zeros, and row i holds the i-th call: its id i, or 0 for
+/// the padding after the last call, its number of rounds, the gas charged
+/// for them, the length of its calldata and its RLC, the RLC of its 64-byte
+/// output, its final block flag, its final block flag byte and whether it
+/// succeeded. An EVM circuit checks a call to the 0x09 precompile with a
+/// single lookup into these columns.
+///
+/// Whether a call succeeds is decided by its length and its flag byte alone,
+/// both bound to the RLC of its calldata. When the length is
+/// `EIP152_INPUT_LENGTH`, the calldata is the input of the compression of
+/// the call, flag byte last, so a valid call cannot be claimed to fail.
+/// Otherwise, the flag byte and the number of rounds are 0. A failed call
+/// has 0 for its output RLC and final block flag.
+///
+/// EIP-152 charges `EIP152_GAS_PER_ROUND` gas per round, which is 1, so the
+/// gas column is a copy of the rounds column.
+#[derive(Clone, Copy, Debug)]
+pub struct Blake2fTable {
+    pub id: Column<Advice>,
+    pub rounds: Column<Advice>,
+    pub gas: Column<Advice>,
+    pub length: Column<Advice>,
+    pub input_rlc: Column<Advice>,
+    pub output_rlc: Column<Advice>,
+    pub f: Column<Advice>,
+    pub flag: Column<Advice>,
+    pub success: Column<Advice>,
+    /// The randomness both RLCs are taken with
+    pub challenge: Challenge,
+}
+
+/// The cells of a proven call that make up its row of the table.
+#[derive(Clone, Debug)]
+pub struct Blake2fTableRow<F: FieldExt> {
+    pub id: AssignedCell<F, F>,
+    pub rounds: AssignedCell<F, F>,
+    pub length: AssignedCell<F, F>,
+    pub input_rlc: AssignedCell<F, F>,
+    pub output_rlc: AssignedCell<F, F>,
+    pub f: AssignedCell<F, F>,
+    pub flag: AssignedCell<F, F>,
+    pub success: AssignedCell<F, F>,
+}
+
+impl Blake2fTable {
+    pub fn construct<F: FieldExt>(meta: &mut ConstraintSystem<F>, challenge: Challenge) -> Self {
+        let table = Self {
+            id: meta.advice_column(),
+            rounds: meta.advice_column(),
+            gas: meta.advice_column(),
+            length: meta.advice_column(),
+            input_rlc: meta.advice_column_in(SecondPhase),
+            output_rlc: meta.advice_column_in(SecondPhase),
+            f: meta.advice_column(),
+            flag: meta.advice_column(),
+            success: meta.advice_column(),
+            challenge,
+        };
+        for column in table.advice_columns() {
+            meta.enable_equality(column);
+        }
+        table
+    }
+
+    fn advice_columns(&self) -> [Column<Advice>; 9] {
+        [
+            self.id,
+            self.rounds,
+            self.gas,
+            self.length,
+            self.input_rlc,
+            self.output_rlc,
+            self.f,
+            self.flag,
+            self.success,
+        ]
+    }
+
+    pub fn columns(&self) -> Vec<Column<Any>> {
+        self.advice_columns().into_iter().map(|column| column.into()).collect()
+    }
+
+    pub fn annotations(&self) -> Vec<String> {
+        ["id", "rounds", "gas", "length", "input_rlc", "output_rlc", "f", "flag", "success"]
+            .into_iter()
+            .map(String::from)
+            .collect()
+    }
+
+    /// Returns the columns of the table queried on the current row, to be
+    /// looked up into.
+    pub fn table_exprs<F: FieldExt>(&self, meta: &mut VirtualCells<'_, F>) -> Vec<Expression<F>> {
+        self.advice_columns()
+            .into_iter()
+            .map(|column| meta.query_advice(column, Rotation::cur()))
+            .collect()
+    }
+
+    /// Fills the table with the zero row followed by one row per call,
+    /// copied from the cells the chip proved them with. The gas of a call
+    /// is copied from its rounds.
+    pub(crate) fn assign<F: FieldExt>(
+        &self,
+        layouter: &mut impl Layouter<F>,
+        rows: &[Blake2fTableRow<F>],
+    ) -> Result<(), Error> {
+        layouter.assign_region(
+            || "blake2f table",
+            |mut region| {
+                for (column, annotation) in self.advice_columns().into_iter().zip(self.annotations()) {
+                    region.assign_advice(|| annotation.clone(), column, 0, || Value::known(F::zero()))?;
+                }
+
+                for (idx, row) in rows.iter().enumerate() {
+                    let offset = idx + 1;
+                    row.id.copy_advice(|| "id", &mut region, self.id, offset)?;
+                    row.rounds.copy_advice(|| "rounds", &mut region, self.rounds, offset)?;
+                    row.rounds.copy_advice(|| "gas", &mut region, self.gas, offset)?;
+                    row.length.copy_advice(|| "length", &mut region, self.length, offset)?;
+                    row.input_rlc.copy_advice(|| "input_rlc", &mut region, self.input_rlc, offset)?;
+                    row.output_rlc.copy_advice(|| "output_rlc", &mut region, self.output_rlc, offset)?;
+                    row.f.copy_advice(|| "f", &mut region, self.f, offset)?;
+                    row.flag.copy_advice(|| "flag", &mut region, self.flag, offset)?;
+                    row.success.copy_advice(|| "success", &mut region, self.success, offset)?;
+                }
+                Ok(())
+            },
+        )
+    }
+}
+
+/// Accumulates the random linear combination of a sequence of bytes, one
+/// byte per row, starting from 0 on the row above the first byte:
+///
+/// | byte   | acc                   |       |
+/// |--------|-----------------------|-------|
+/// |        | 0                     |       |
+/// | b_0    | b_0                   | s_rlc |
+/// | b_1    | b_0 * r + b_1         | s_rlc |
+/// | ...    | ...                   | s_rlc |
+///
+/// The bytes are copied in from cells that are already range-checked. The
+/// same goes for whole 64-bit words, which the round slots take the RLCs of.
+#[derive(Clone, Copy, Debug)]
+pub struct RlcConfig {
+    byte: Column<Advice>,
+    acc: Column<Advice>,
+    s_rlc: Selector,
+    challenge: Challenge,
+}
+
+impl RlcConfig {
+    pub fn configure<F: FieldExt>(meta: &mut ConstraintSystem<F>, challenge: Challenge) -> Self {
+        let byte = meta.advice_column();
+        let acc = meta.advice_column_in(SecondPhase);
+        meta.enable_equality(byte);
+        meta.enable_equality(acc);
+        let s_rlc = meta.selector();
+
+        meta.create_gate("rlc", |meta| {
+            let s_rlc = meta.query_selector(s_rlc);
+            let r = meta.query_challenge(challenge);
+            let byte = meta.query_advice(byte, Rotation::cur());
+            let acc_prev = meta.query_advice(acc, Rotation::prev());
+            let acc = meta.query_advice(acc, Rotation::cur());
+            vec![s_rlc * (acc_prev * r + byte - acc)]
+        });
+
+        Self {
+            byte,
+            acc,
+            s_rlc,
+            challenge,
+        }
+    }
+
+    /// Returns a cell holding the RLC of `bytes`, the first byte getting the
+    /// highest power of the randomness.
+    pub fn assign<F: FieldExt>(
+        &self,
+        layouter: &mut impl Layouter<F>,
+        bytes: &[AssignedCell<F, F>],
+    ) -> Result<AssignedCell<F, F>, Error> {
+        let r = layouter.get_challenge(self.challenge);
+        layouter.assign_region(
+            || "rlc",
+            |mut region| {
+                let mut acc = region.assign_advice(|| "acc", self.acc, 0, || Value::known(F::zero()))?;
+                region.constrain_constant(acc.cell(), F::zero())?;
+
+                for (idx, byte) in bytes.iter().enumerate() {
+                    let offset = idx + 1;
+                    self.s_rlc.enable(&mut region, offset)?;
+                    byte.copy_advice(|| "byte", &mut region, self.byte, offset)?;
+                    let value = acc.value().copied() * r + byte.value().copied();
+                    acc = region.assign_advice(|| "acc", self.acc, offset, || value)?;
+                }
+                Ok(acc)
+            },
+        )
+    }
+}
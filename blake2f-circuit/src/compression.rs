@@ -1,23 +1,19 @@
 use halo2_proofs::{
-    circuit::{Layouter},
-    //pasta::pallas,
-    plonk::{Advice, Column, Constraint, Constraints, ConstraintSystem, Error, Expression, Selector, Instance},
-    poly::Rotation, arithmetic::FieldExt,
+    arithmetic::FieldExt,
+    circuit::{AssignedCell, Layouter, Region, Value},
+    plonk::{Advice, Column, ConstraintSystem, Error, Expression, Instance, Selector},
+    poly::Rotation,
 };
 
-use group::ff::{Field, PrimeField};
-use std::marker::PhantomData;
-
 mod compression_gate;
 mod bit_chunk;
 
-use pasta_curves::pallas::Base;
-
-const ROUNDS: usize = 12;
-const STATE: usize = 8;
+pub(crate) const STATE: usize = 8;
 
 use compression_gate::CompressionGate;
-use bit_chunk::BitChunkSpread;
+use crate::utils::sum_with_carry;
+use crate::xor_chunks::{DecomposedWord, SpreadTableConfig, XorChip, XorConfig, CHUNK_SIZE, NUM_CHUNKS};
+use bit_chunk::{MAX_SUB_PIECES, ROTR_63};
 
 // BLAKE2 Sigma constant
 pub const BLAKE2B_SIGMA: [[u8; 16]; 10] = [
@@ -44,40 +40,192 @@ pub const BLAKE2B_IV: [u64; STATE] = [
     0x5be0cd19137e2179,
 ];
 
+// Right-rotation amounts of the G function, in the order they are applied
+pub const BLAKE2B_ROTATIONS: [usize; 4] = [32, 24, 16, 63];
+
+// Rotations an addition can read its first operand in place with: none, or
+// one of the rotations by a whole number of chunks
+const ACC_ROTATIONS: [usize; 4] = [0, 32, 24, 16];
+
+// Working vector indices (a, b, c, d) mixed by each of the eight G calls of a round.
+// The first four mix the columns of v, the last four its diagonals.
+const G_INDICES: [[usize; 4]; 8] = [
+    [0, 4, 8, 12],
+    [1, 5, 9, 13],
+    [2, 6, 10, 14],
+    [3, 7, 11, 15],
+    [0, 5, 10, 15],
+    [1, 6, 11, 12],
+    [2, 7, 8, 13],
+    [3, 4, 9, 14],
+];
+
+/// The BLAKE2b F compression function, computed natively: compresses the
+/// message block `m` into `h` in `rounds` rounds, with offset counter `t`
+/// and final block flag `f`.
+pub fn compress_native(
+    rounds: u32,
+    h: [u64; STATE],
+    m: [u64; 16],
+    t: [u64; 2],
+    f: bool,
+) -> [u64; STATE] {
+    let mut v = work_vector_native(h, t, f);
+    for round in 0..rounds as usize {
+        round_native(&mut v, message_schedule(&m, round));
+    }
+    feed_forward_native(h, &v)
+}
+
+/// The work vector v[0..15] a compression starts its rounds from.
+pub fn work_vector_native(h: [u64; STATE], t: [u64; 2], f: bool) -> [u64; 16] {
+    let mut v = [0; 16];
+    v[..STATE].copy_from_slice(&h);
+    v[STATE..].copy_from_slice(&BLAKE2B_IV);
+    v[12] ^= t[0];
+    v[13] ^= t[1];
+    if f {
+        v[14] = !v[14];
+    }
+    v
+}
+
+/// The message words of a round in the order its G calls take them, two
+/// per call.
+pub fn message_schedule<T: Clone>(m: &[T; 16], round: usize) -> [T; 16] {
+    BLAKE2B_SIGMA[round % 10].map(|idx| m[idx as usize].clone())
+}
+
+/// Applies a single round to the work vector `v`, with the message words
+/// `x` of the round as given by `message_schedule`.
+pub fn round_native(v: &mut [u64; 16], x: [u64; 16]) {
+    for (idx, indices) in G_INDICES.iter().enumerate() {
+        g_native(v, *indices, x[2 * idx], x[2 * idx + 1]);
+    }
+}
+
+/// h'[i] = h[i] ^ v[i] ^ v[i + 8], the output of a compression.
+pub fn feed_forward_native(h: [u64; STATE], v: &[u64; 16]) -> [u64; STATE] {
+    let mut out = [0; STATE];
+    for (idx, word) in out.iter_mut().enumerate() {
+        *word = h[idx] ^ v[idx] ^ v[idx + STATE];
+    }
+    out
+}
+
+fn g_native(v: &mut [u64; 16], [a, b, c, d]: [usize; 4], x: u64, y: u64) {
+    let [r1, r2, r3, r4] = BLAKE2B_ROTATIONS.map(|n| n as u32);
+    v[a] = v[a].wrapping_add(v[b]).wrapping_add(x);
+    v[d] = (v[d] ^ v[a]).rotate_right(r1);
+    v[c] = v[c].wrapping_add(v[d]);
+    v[b] = (v[b] ^ v[c]).rotate_right(r2);
+    v[a] = v[a].wrapping_add(v[b]).wrapping_add(y);
+    v[d] = (v[d] ^ v[a]).rotate_right(r3);
+    v[c] = v[c].wrapping_add(v[d]);
+    v[b] = (v[b] ^ v[c]).rotate_right(r4);
+}
+
+/// A 64-bit word assigned in the compression region, along with its value.
+///
+/// A word rotated by a whole number of chunks is not assigned anywhere: it
+/// reuses the chunk cells of the word it was rotated from, in their rotated
+/// order, and `rotation` records how far they are from the row they live on.
+#[derive(Clone, Debug)]
+pub struct AssignedWord<F: FieldExt> {
+    cell: AssignedCell<F, F>,
+    chunks: [AssignedCell<F, F>; NUM_CHUNKS],
+    rotation: usize,
+    value: Value<u64>,
+}
+
+impl<F: FieldExt> AssignedWord<F> {
+    fn new(word: DecomposedWord<F>, value: Value<u64>) -> Self {
+        AssignedWord {
+            cell: word.word,
+            chunks: word.dense,
+            rotation: 0,
+            value,
+        }
+    }
+
+    /// Returns the cell holding the word, or `None` if the word was only
+    /// obtained by rewiring the chunks of another one.
+    pub fn cell(&self) -> Option<&AssignedCell<F, F>> {
+        (self.rotation == 0).then_some(&self.cell)
+    }
+
+    /// Returns the cells of the bytes of the word, least significant first.
+    pub fn chunks(&self) -> &[AssignedCell<F, F>; NUM_CHUNKS] {
+        &self.chunks
+    }
+
+    pub fn value(&self) -> Value<u64> {
+        self.value
+    }
+}
+
 // This is where we will define the message and state chunks that serve as inputs to the compression function
 #[derive(Clone, Debug)]
 pub struct MessageChunk(u64);
 
+// A u64 word of the state, held as an assigned field element
+#[derive(Clone, Debug)]
+pub struct StateChunk<F: FieldExt>(AssignedWord<F>);
+
+impl<F: FieldExt> StateChunk<F> {
+    pub fn word(&self) -> &AssignedWord<F> {
+        &self.0
+    }
+}
+
+/// The message block, offset counter and final block flag of a compression,
+/// as assigned in its region.
 #[derive(Clone, Debug)]
-// This type should be a u64 field element
-pub struct StateChunk(u64);
+pub struct Block<F: FieldExt> {
+    m: [AssignedWord<F>; 16],
+    t: [AssignedWord<F>; 2],
+    f: AssignedCell<F, F>,
+}
+
+impl<F: FieldExt> Block<F> {
+    pub fn message(&self) -> &[AssignedWord<F>; 16] {
+        &self.m
+    }
+
+    pub fn counter(&self) -> &[AssignedWord<F>; 2] {
+        &self.t
+    }
 
+    pub fn last_block(&self) -> &AssignedCell<F, F> {
+        &self.f
+    }
+}
 
 /// The internal state for BLAKE2. Represents the h[0..7] internal state of the hash
 #[derive(Clone, Debug)]
-pub struct State {
-    a: Option<StateChunk>,
-    b: Option<StateChunk>,
-    c: Option<StateChunk>,
-    d: Option<StateChunk>,
-    e: Option<StateChunk>,
-    f: Option<StateChunk>,
-    g: Option<StateChunk>,
-    h: Option<StateChunk>,
+pub struct State<F: FieldExt> {
+    a: Option<StateChunk<F>>,
+    b: Option<StateChunk<F>>,
+    c: Option<StateChunk<F>>,
+    d: Option<StateChunk<F>>,
+    e: Option<StateChunk<F>>,
+    f: Option<StateChunk<F>>,
+    g: Option<StateChunk<F>>,
+    h: Option<StateChunk<F>>,
 }
 
-impl State {
+impl<F: FieldExt> State<F> {
     #[allow(clippy::many_single_char_names)]
     #[allow(clippy::too_many_arguments)]
     pub fn new(
-        a: StateChunk,
-        b: StateChunk,
-        c: StateChunk,
-        d: StateChunk,
-        e: StateChunk,
-        f: StateChunk,
-        g: StateChunk,
-        h: StateChunk,
+        a: StateChunk<F>,
+        b: StateChunk<F>,
+        c: StateChunk<F>,
+        d: StateChunk<F>,
+        e: StateChunk<F>,
+        f: StateChunk<F>,
+        g: StateChunk<F>,
+        h: StateChunk<F>,
     ) -> Self {
         State {
             a: Some(a),
@@ -104,169 +252,1273 @@ impl State {
         }
     }
 
+    fn from_words(words: [AssignedWord<F>; STATE]) -> Self {
+        let [a, b, c, d, e, f, g, h] = words.map(StateChunk);
+        State::new(a, b, c, d, e, f, g, h)
+    }
+
+    /// Returns the eight words h[0..7] of the state, or a synthesis error if
+    /// any of them has not been assigned yet.
+    pub fn words(&self) -> Result<[AssignedWord<F>; STATE], Error> {
+        let chunks = [&self.a, &self.b, &self.c, &self.d, &self.e, &self.f, &self.g, &self.h];
+        let words = chunks
+            .iter()
+            .map(|chunk| chunk.as_ref().map(|chunk| chunk.0.clone()).ok_or(Error::Synthesis))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(words.try_into().unwrap())
+    }
 }
 
+/// Every 64-bit word of the compression lives in its own row, decomposed
+/// into 8-bit spread chunks by the XOR chip. The chunks double as the range
+/// check of the word.
+///
+/// Operations are laid out as chains: the first operand of an operation is
+/// the result of the previous one, read in place from the row above, and
+/// only the remaining operands are copied in. Rotations by 32, 24 and 16 are
+/// whole numbers of chunks, so they take no rows: the next addition reads
+/// the chunks of the row above in their rotated order, and later copies of
+/// the rotated word are made chunk by chunk. The rotation by 63 splits the
+/// top chunk of the word into sub-pieces witnessed next to it, as given by
+/// `ROTR_63`. An addition, an XOR and a byte rotation followed by another
+/// addition look like:
+///
+/// | word  | chunks        | sub_pieces   | carry |                |
+/// |-------|---------------|--------------|-------|----------------|
+/// | acc   | chunks of acc |              |       | s_add3[0]      |
+/// | y     | chunks of y   |              |       |                |
+/// | z     | chunks of z   |              |       |                |
+/// | sum   | chunks of sum |              | carry | q_xor          |
+/// | w     | chunks of w   |              |       |                |
+/// | and   | chunks of and |              |       |                |
+/// | xor   | chunks of xor |              |       | s_add2[rotr]   |
+/// | c     | chunks of c   |              |       |                |
+/// | sum   | chunks of sum |              | carry |                |
+///
+/// while the rotation by 63 of a word takes the row after it:
+///
+/// | word  | chunks        | sub_pieces   |          |
+/// |-------|---------------|--------------|----------|
+/// | xor   | chunks of xor | split chunk  | s_rotr63 |
+/// | out   | chunks of out |              |          |
+///
+/// Up to a maximum number of rounds are laid out, each with a flag that is
+/// set for the first `rounds` of them. The flags are witnessed in the carry
+/// column next to a running count of the set ones, and at the end of every
+/// round each word of v is selected from before or after the round:
+///
+/// | word  | chunks        | sub_pieces   | carry |          |
+/// |-------|---------------|--------------|-------|----------|
+/// | 0     |               |              | 1     |          |
+/// | count |               |              | flag  | s_rounds |
+/// | ...   |               |              | ...   | s_rounds |
+/// | out   | chunks of out | old, new     | flag  | s_select |
+///
+/// The rounds can also be laid out one per region instead, from the work
+/// vector a compression is initialized with to the one it is finalized
+/// from, for a chip that shares them among its compressions.
+///
+/// The final block flag f sits in the carry column next to the mask it
+/// XORs into v[14], which is all ones if f is set and zero otherwise.
+///
+/// For comparison, the byte-aligned rotations can also be laid out the way
+/// they used to be, each copying the rotated word onto a row of its own.
+#[derive(Clone, Debug)]
 pub struct CompressionConfig {
-    lookup: PhantomData<()>,
-    //TODO: define advice and selectors
-    // todo what the selectors be used for
-    advice: [Column<Advice>; 16],
-    s1: Selector,
-    s2: Selector,
-    s3: Selector,
-    s4: Selector,
+    xor: XorConfig,
+    sub_pieces: [Column<Advice>; MAX_SUB_PIECES],
+    carry: Column<Advice>,
+    s_add2: [Selector; 4],
+    s_add3: [Selector; 4],
+    s_rotr63: Selector,
+    s_rounds: Selector,
+    s_select: Selector,
+    s_final: Selector,
+    byte_rotations_laid_out: bool,
 }
 
-impl CompressionConfig { pub(super) fn configure(
-    meta: &mut ConstraintSystem<Base>,
-    message: [Column<Advice>; 16],
-) -> Self {
-    // Define advice columns
-    let advice = (0..16)
-        .map(|_| meta.advice_column())
-        .collect::<Vec<_>>()
-        .try_into()
-        .unwrap();
-
-    // Define selectors
-    let r1 = meta.selector();
-    let r2 = meta.selector();
-    let r3 = meta.selector();
-    let r4 = meta.selector();
-
-    // Return the CompressionConfig struct
-    Self {
-        lookup: PhantomData,
-        advice,
-        s1,
-        s2,
-        s3,
-        s4,
-    }
-}
+impl CompressionConfig {
+    pub(super) fn configure<F: FieldExt>(meta: &mut ConstraintSystem<F>) -> Self {
+        let word = meta.advice_column();
+        let sub_pieces = [(); MAX_SUB_PIECES].map(|_| meta.advice_column());
+        let carry = meta.advice_column();
+        meta.enable_equality(word);
+        meta.enable_equality(carry);
+        for column in sub_pieces.iter() {
+            meta.enable_equality(*column);
+        }
 
+        let constants = meta.fixed_column();
+        meta.enable_constant(constants);
 
-    fn blake2_g<F: FieldExt>(
-        &self,
-        meta: &mut ConstraintSystem<Base>,
-        v: &mut [Expression<F>; 16],
-        a: usize,
-        b: usize,
-        c: usize,
-        d: usize,
-        x: Expression<F>,
-        y: Expression<F>,
-    ) -> Vec<Expression<F>> {
-
-        // todo are the lookups required for const r1,r2,r3 and r4? i dont think so check?
-        //selector column
-        let r1 = meta.lookup_table_column(r1);
-        meta.lookup(|meta| {
-            let r_1 = meta.query_any(a, Rotation::cur());
-            vec![(r_1, r1)]
-        });
-        let r2 = meta.lookup_table_column(r2);
-        meta.lookup(|meta| {
-            let r_2 = meta.query_any(a, Rotation::cur());
-            vec![(r_2, r2)]
+        let table = SpreadTableConfig::configure(meta);
+        let xor = XorChip::configure(meta, word, table);
+
+        let s_add2 = [(); 4].map(|_| meta.selector());
+        let s_add3 = [(); 4].map(|_| meta.selector());
+        let s_rotr63 = meta.complex_selector();
+        let s_rounds = meta.selector();
+        let s_select = meta.selector();
+        let s_final = meta.selector();
+
+        for (name, selectors, num_operands) in [("add2", s_add2, 2), ("add3", s_add3, 3)] {
+            for (s_add, rotation) in selectors.into_iter().zip(ACC_ROTATIONS) {
+                meta.create_gate(name, |meta| {
+                    let s_add = meta.query_selector(s_add);
+                    let chunks = xor.dense.map(|chunk| meta.query_advice(chunk, Rotation::cur()));
+                    let operands = std::iter::once(CompressionGate::rotated_word(&chunks, rotation))
+                        .chain((1..num_operands).map(|idx| meta.query_advice(word, Rotation(idx))))
+                        .collect::<Vec<_>>();
+                    let sum = meta.query_advice(word, Rotation(num_operands));
+                    let carry = meta.query_advice(carry, Rotation(num_operands));
+                    CompressionGate::s_add(s_add, &operands, sum, carry)
+                });
+            }
+        }
+
+        meta.create_gate("rotr_63", |meta| {
+            let s_rotr63 = meta.query_selector(s_rotr63);
+            let chunks = xor.dense.map(|chunk| meta.query_advice(chunk, Rotation::cur()));
+            let sub_pieces = sub_pieces.map(|piece| meta.query_advice(piece, Rotation::cur()));
+            let out = meta.query_advice(word, Rotation::next());
+            CompressionGate::s_rotr(s_rotr63, &chunks, &sub_pieces, out, ROTR_63)
         });
-        let r3 = meta.lookup_table_column(r3);
-        meta.lookup(|meta| {
-            let r_3 = meta.query_any(a, Rotation::cur());
-            vec![(r_3, r3)]
+
+        // A sub-piece of width w is range-checked by looking up both the
+        // piece and the piece shifted left by 8 - w bits in the spread table
+        for (sub_piece, width) in sub_pieces.iter().zip(ROTR_63.sub_widths()) {
+            meta.lookup("sub-piece", |meta| {
+                let s_rotr63 = meta.query_selector(s_rotr63);
+                let sub_piece = meta.query_advice(*sub_piece, Rotation::cur());
+                let shift = Expression::Constant(F::from(1 << (CHUNK_SIZE - width)));
+                vec![
+                    (s_rotr63.clone() * sub_piece.clone(), table.dense),
+                    (s_rotr63 * sub_piece * shift, table.dense),
+                ]
+            });
+        }
+
+        meta.create_gate("rounds", |meta| {
+            let s_rounds = meta.query_selector(s_rounds);
+            let flag_prev = meta.query_advice(carry, Rotation::prev());
+            let flag = meta.query_advice(carry, Rotation::cur());
+            let count_prev = meta.query_advice(word, Rotation::prev());
+            let count = meta.query_advice(word, Rotation::cur());
+            CompressionGate::s_rounds(s_rounds, flag_prev, flag, count_prev, count)
         });
-        let r4 = meta.lookup_table_column(r4);
-        meta.lookup(|meta| {
-            let r_4 = meta.query_any(a, Rotation::cur());
-            vec![(r_4, r4)]
+
+        meta.create_gate("select", |meta| {
+            let s_select = meta.query_selector(s_select);
+            let flag = meta.query_advice(carry, Rotation::cur());
+            let old = meta.query_advice(sub_pieces[0], Rotation::cur());
+            let new = meta.query_advice(sub_pieces[1], Rotation::cur());
+            let out = meta.query_advice(word, Rotation::cur());
+            CompressionGate::s_select(s_select, flag, old, new, out)
         });
 
-        meta.create_gate("blake2_g", |meta| {
-            
-            let v = (0..16)
-                .map(|i| meta.query_advice(format!("v_{}", i), Rotation::cur()))
-                .collect::<Vec<_>>()
-                .try_into()
-                .unwrap();
-            let a = meta.query_advice("a", Rotation::cur());
-            let b = meta.query_advice("b", Rotation::cur());
-            let c = meta.query_advice("c", Rotation::cur());
-            let d = meta.query_advice("d", Rotation::cur());
-            let x = meta.query_advice("x", Rotation::cur());
-            let y = meta.query_advice("y", Rotation::cur());
-
-            // why query selectors for constants
-            // use query table column instead
-            let r1 = meta.query_selector("r1");
-            let r2 = meta.query_selector("r2");
-            let r3 = meta.query_selector("r3");
-            let r4 = meta.query_selector("r4");
-
-        
-            let updated_v = CompressionGate::g_func(&mut v, a, b, c, d, x, y);
-        
-            for i in 0..16 {
-                meta.assign_advice(
-                    format!("v_{}_updated", i),
-                    Rotation::cur(),
-                    updated_v[i].into(),
-                );
-            }
+        meta.create_gate("final block", |meta| {
+            let s_final = meta.query_selector(s_final);
+            let f = meta.query_advice(carry, Rotation::cur());
+            let mask = meta.query_advice(word, Rotation::cur());
+            CompressionGate::s_final(s_final, f, mask)
         });
+
+        Self {
+            xor,
+            sub_pieces,
+            carry,
+            s_add2,
+            s_add3,
+            s_rotr63,
+            s_rounds,
+            s_select,
+            s_final,
+            byte_rotations_laid_out: false,
+        }
+    }
+
+    /// Lays out every rotation by a whole number of chunks on a row of its
+    /// own instead of rewiring the chunks of the rotated word.
+    pub(super) fn lay_out_byte_rotations(&mut self) {
+        self.byte_rotations_laid_out = true;
     }
 
-    // todo written by chatgpt - check if this makes sense
-    fn compression_function(&self, 
-        layouter: &mut impl Layouter<Base>
+    /// Loads the spread table used by every word decomposition.
+    pub(super) fn load<F: FieldExt>(&self, layouter: &mut impl Layouter<F>) -> Result<(), Error> {
+        self.xor.table.load(layouter)
+    }
+
+    /// Assigns the initial state h[0..7] of a compression from its witness.
+    pub(super) fn assign_state<F: FieldExt>(
+        &self,
+        layouter: &mut impl Layouter<F>,
+        h: [Value<u64>; STATE],
+    ) -> Result<State<F>, Error> {
+        layouter.assign_region(
+            || "assign state",
+            |mut region| {
+                let mut words = Vec::with_capacity(STATE);
+                for (offset, value) in h.iter().enumerate() {
+                    words.push(self.assign_word(&mut region, offset, *value)?);
+                }
+                Ok(State::from_words(words.try_into().unwrap()))
+            },
+        )
+    }
+
+    /// Copies `state` into a new region, each word constrained to equal the
+    /// one it is copied from, so a compression can start from the state the
+    /// previous one ended with.
+    pub(super) fn copy_state<F: FieldExt>(
+        &self,
+        layouter: &mut impl Layouter<F>,
+        state: &State<F>,
+    ) -> Result<State<F>, Error> {
+        let words = state.words()?;
+        layouter.assign_region(
+            || "copy state",
+            |mut region| {
+                let mut copies = Vec::with_capacity(STATE);
+                for (offset, word) in words.iter().enumerate() {
+                    copies.push(self.copy_word(&mut region, offset, word)?);
+                }
+                Ok(State::from_words(copies.try_into().unwrap()))
+            },
+        )
+    }
+
+    /// Reads the number of rounds of a compression from `row` of `instance`,
+    /// and decomposes it into bytes like any other word. EIP-152 encodes it
+    /// in 4 bytes, so its top four chunks are fixed to zero.
+    pub(super) fn assign_rounds<F: FieldExt>(
+        &self,
+        layouter: &mut impl Layouter<F>,
+        instance: Column<Instance>,
+        row: usize,
+    ) -> Result<AssignedWord<F>, Error> {
+        layouter.assign_region(
+            || "rounds",
+            |mut region| {
+                let rounds =
+                    region.assign_advice_from_instance(|| "rounds", instance, row, self.carry, 0)?;
+                let dense = rounds
+                    .value()
+                    .map(|rounds| (rounds.get_lower_128() as u64).to_le_bytes().map(u64::from));
+                self.decompose_rounds(&mut region, &rounds, dense)
+            },
+        )
+    }
+
+    // Decomposes the number of rounds in `rounds` into the chunks `dense` on
+    // the first row of the region, with the top four chunks fixed to zero
+    fn decompose_rounds<F: FieldExt>(
+        &self,
+        region: &mut Region<'_, F>,
+        rounds: &AssignedCell<F, F>,
+        dense: Value<[u64; NUM_CHUNKS]>,
+    ) -> Result<AssignedWord<F>, Error> {
+        let word = self.xor.assign_decomposition(region, 0, rounds.value().copied(), dense)?;
+        region.constrain_equal(rounds.cell(), word.word.cell())?;
+        for chunk in word.dense[NUM_CHUNKS / 2..].iter() {
+            region.constrain_constant(chunk.cell(), F::zero())?;
+        }
+        let value = rounds.value().map(|rounds| rounds.get_lower_128() as u64);
+        Ok(AssignedWord::new(word, value))
+    }
+
+    /// Returns `len` flags of which the first `count` are set, laid out like
+    /// the flags of the rounds of a compression. The count cannot exceed `len`.
+    pub(super) fn assign_flags<F: FieldExt>(
+        &self,
+        layouter: &mut impl Layouter<F>,
+        count: &AssignedWord<F>,
+        len: usize,
+    ) -> Result<Vec<AssignedCell<F, F>>, Error> {
+        layouter.assign_region(
+            || "flags",
+            |mut region| self.assign_round_flags(&mut region, &mut 0, count, len),
+        )
+    }
+
+    /// Assigns the BLAKE2b IV as a state, with every word fixed to its constant.
+    pub(super) fn initialize_with_iv<F: FieldExt>(
+        &self,
+        layouter: &mut impl Layouter<F>,
+    ) -> Result<State<F>, Error> {
+        self.initialize_with_constants(layouter, BLAKE2B_IV)
+    }
+
+    /// Assigns `h` as a state, with every word fixed to its constant.
+    pub(super) fn initialize_with_constants<F: FieldExt>(
+        &self,
+        layouter: &mut impl Layouter<F>,
+        h: [u64; STATE],
+    ) -> Result<State<F>, Error> {
+        layouter.assign_region(
+            || "constant state",
+            |mut region| {
+                let mut words = Vec::with_capacity(STATE);
+                for (offset, word) in h.iter().enumerate() {
+                    words.push(self.assign_constant_word(&mut region, offset, *word)?);
+                }
+                Ok(State::from_words(words.try_into().unwrap()))
+            },
+        )
+    }
+
+    /// Copies `cell` into a new region as a word, which range-checks it to
+    /// 64 bits.
+    pub(super) fn assign_word_from<F: FieldExt>(
+        &self,
+        layouter: &mut impl Layouter<F>,
+        cell: &AssignedCell<F, F>,
+    ) -> Result<AssignedWord<F>, Error> {
+        layouter.assign_region(
+            || "word",
+            |mut region| {
+                let value = cell.value().map(|value| value.get_lower_128() as u64);
+                let word = self.assign_word(&mut region, 0, value)?;
+                region.constrain_equal(cell.cell(), word.cell.cell())?;
+                Ok(word)
+            },
+        )
+    }
+
+    /// Returns `new` if `flag` is set and `old` otherwise for every (flag,
+    /// old, new) of `selections`, each cell holding a word.
+    pub(super) fn select_cells<F: FieldExt>(
+        &self,
+        layouter: &mut impl Layouter<F>,
+        selections: &[(&AssignedCell<F, F>, &AssignedCell<F, F>, &AssignedCell<F, F>)],
+    ) -> Result<Vec<AssignedWord<F>>, Error> {
+        layouter.assign_region(
+            || "select",
+            |mut region| {
+                let mut offset = 0;
+                selections
+                    .iter()
+                    .map(|(flag, old, new)| self.select_cell(&mut region, &mut offset, flag, old, new))
+                    .collect()
+            },
+        )
+    }
+
+    /// Returns `new` if `flag` is set and `old` otherwise, word by word.
+    pub(super) fn select_state<F: FieldExt>(
+        &self,
+        layouter: &mut impl Layouter<F>,
+        flag: &AssignedCell<F, F>,
+        old: &State<F>,
+        new: &State<F>,
+    ) -> Result<State<F>, Error> {
+        let (old, new) = (old.words()?, new.words()?);
+        layouter.assign_region(
+            || "select state",
+            |mut region| {
+                let mut offset = 0;
+                let mut words = Vec::with_capacity(STATE);
+                for (old, new) in old.iter().zip(new.iter()) {
+                    words.push(self.select(&mut region, &mut offset, flag, old, new)?);
+                }
+                Ok(State::from_words(words.try_into().unwrap()))
+            },
+        )
+    }
+
+    /// Assigns a word fixed to `constant`.
+    pub(super) fn assign_constant<F: FieldExt>(
+        &self,
+        layouter: &mut impl Layouter<F>,
+        constant: u64,
+    ) -> Result<AssignedWord<F>, Error> {
+        layouter.assign_region(
+            || "constant",
+            |mut region| self.assign_constant_word(&mut region, 0, constant),
+        )
+    }
+
+    /// Lays out the BLAKE2b F compression function on `state`, with message
+    /// block `m`, offset counter `t` and final block flag `f`, and returns the
+    /// new state h'[0..7] along with the assigned block. Only the first
+    /// `rounds` of the `max_rounds` rounds laid out are applied, so `rounds`
+    /// must be at most `max_rounds`.
+    #[allow(clippy::too_many_arguments)]
+    pub(super) fn compress<F: FieldExt>(
+        &self,
+        layouter: &mut impl Layouter<F>,
+        state: &State<F>,
+        rounds: &AssignedWord<F>,
+        max_rounds: usize,
+        m: [Value<u64>; 16],
+        t: [Value<u64>; 2],
+        f: Value<bool>,
+    ) -> Result<(State<F>, Block<F>), Error> {
+        let h = state.words()?;
+
+        layouter.assign_region(
+            || "blake2b compression",
+            |mut region| {
+                let mut offset = 0;
+
+                let flags = self.assign_round_flags(&mut region, &mut offset, rounds, max_rounds)?;
+
+                let (mut v, block) = self.initialize_in(&mut region, &mut offset, &h, m, t, f)?;
+
+                for (round, flag) in flags.iter().enumerate() {
+                    let previous = v.clone();
+                    let x = message_schedule(&block.m, round);
+                    self.round_in(&mut region, &mut offset, &mut v, &x)?;
+
+                    // Rounds past `rounds` leave v as it was
+                    for (old, new) in previous.iter().zip(v.iter_mut()) {
+                        *new = self.select(&mut region, &mut offset, flag, old, new)?;
+                    }
+                }
+
+                let words = self.feed_forward(&mut region, &mut offset, &h, &v)?;
+                Ok((State::from_words(words), block))
+            },
+        )
+    }
+
+    /// Lays out the start of a compression on `state`, with message block
+    /// `m`, offset counter `t` and final block flag `f`, and returns the work
+    /// vector v[0..15] its rounds start from along with the assigned block.
+    pub(super) fn initialize<F: FieldExt>(
+        &self,
+        layouter: &mut impl Layouter<F>,
+        state: &State<F>,
+        m: [Value<u64>; 16],
+        t: [Value<u64>; 2],
+        f: Value<bool>,
+    ) -> Result<([AssignedWord<F>; 16], Block<F>), Error> {
+        let h = state.words()?;
+
+        layouter.assign_region(
+            || "blake2b initialization",
+            |mut region| self.initialize_in(&mut region, &mut 0, &h, m, t, f),
+        )
+    }
+
+    /// Assigns the work vector a round starts from: `fresh` if `first` is
+    /// set, and `previous`, the output of the round before, otherwise. Without
+    /// a previous round the fresh words are taken as they are. Returns the
+    /// flag along with the vector.
+    pub(super) fn assign_work_vector<F: FieldExt>(
+        &self,
+        layouter: &mut impl Layouter<F>,
+        first: Value<bool>,
+        previous: Option<&[AssignedWord<F>; 16]>,
+        fresh: [Value<u64>; 16],
+    ) -> Result<(AssignedCell<F, F>, [AssignedWord<F>; 16]), Error> {
+        layouter.assign_region(
+            || "work vector",
+            |mut region| {
+                let first = region.assign_advice(
+                    || "first",
+                    self.carry,
+                    0,
+                    || first.map(|first| F::from(first as u64)),
+                )?;
+                let mut v = Vec::with_capacity(16);
+                for (offset, value) in fresh.iter().enumerate() {
+                    v.push(self.assign_word(&mut region, offset, *value)?);
+                }
+                let mut v: [AssignedWord<F>; 16] = v.try_into().unwrap();
+
+                if let Some(previous) = previous {
+                    let mut offset = 16;
+                    for (old, new) in previous.iter().zip(v.iter_mut()) {
+                        *new = self.select(&mut region, &mut offset, &first, old, new)?;
+                    }
+                }
+                Ok((first, v))
+            },
+        )
+    }
+
+    /// Lays out a single round on the work vector `v`, with the message words
+    /// `x` of the round as given by `message_schedule`. Returns the assigned
+    /// message words along with v after the round, whose words are all laid
+    /// out in place so they can be copied as they are.
+    pub(super) fn round<F: FieldExt>(
+        &self,
+        layouter: &mut impl Layouter<F>,
+        v: &[AssignedWord<F>; 16],
+        x: [Value<u64>; 16],
+    ) -> Result<([AssignedWord<F>; 16], [AssignedWord<F>; 16]), Error> {
+        layouter.assign_region(
+            || "blake2b round",
+            |mut region| {
+                let mut x_words = Vec::with_capacity(16);
+                for (offset, value) in x.iter().enumerate() {
+                    x_words.push(self.assign_word(&mut region, offset, *value)?);
+                }
+                let x_words: [AssignedWord<F>; 16] = x_words.try_into().unwrap();
+                let mut offset = 16;
+
+                let mut v = v.clone();
+                self.round_in(&mut region, &mut offset, &mut v, &x_words)?;
+                for word in v.iter_mut() {
+                    if word.rotation != 0 {
+                        *word = self.start_chain(&mut region, &mut offset, word)?;
+                    }
+                }
+                Ok((x_words, v))
+            },
+        )
+    }
+
+    /// Assigns sixteen words of a work vector from their values.
+    pub(super) fn assign_work_words<F: FieldExt>(
+        &self,
+        layouter: &mut impl Layouter<F>,
+        v: [Value<u64>; 16],
+    ) -> Result<[AssignedWord<F>; 16], Error> {
+        layouter.assign_region(
+            || "work vector words",
+            |mut region| {
+                let mut words = Vec::with_capacity(16);
+                for (offset, value) in v.iter().enumerate() {
+                    words.push(self.assign_word(&mut region, offset, *value)?);
+                }
+                Ok(words.try_into().unwrap())
+            },
+        )
+    }
+
+    /// Lays out the end of a compression of `state`, from the work vector
+    /// `v_final` after its rounds if `applied` is set, or from `v_init`, the
+    /// one it started from, otherwise. Returns the new state h'[0..7].
+    pub(super) fn finalize<F: FieldExt>(
+        &self,
+        layouter: &mut impl Layouter<F>,
+        state: &State<F>,
+        v_init: &[AssignedWord<F>; 16],
+        applied: &AssignedCell<F, F>,
+        v_final: &[AssignedWord<F>; 16],
+    ) -> Result<State<F>, Error> {
+        let h = state.words()?;
+
+        layouter.assign_region(
+            || "blake2b finalization",
+            |mut region| {
+                let mut offset = 0;
+                let mut v = Vec::with_capacity(16);
+                for (old, new) in v_init.iter().zip(v_final.iter()) {
+                    v.push(self.select(&mut region, &mut offset, applied, old, new)?);
+                }
+                let v: [AssignedWord<F>; 16] = v.try_into().unwrap();
+
+                let output = self.feed_forward(&mut region, &mut offset, &h, &v)?;
+                Ok(State::from_words(output))
+            },
+        )
+    }
+
+    // Initializes the work vector v[0..15] with h[0..7] and IV[0..7], then
+    // mixes t and f into it. The message block is assigned once, and every G
+    // call copies its two words in, so all rounds see the same block.
+    fn initialize_in<F: FieldExt>(
+        &self,
+        region: &mut Region<'_, F>,
+        offset: &mut usize,
+        h: &[AssignedWord<F>; STATE],
+        m: [Value<u64>; 16],
+        t: [Value<u64>; 2],
+        f: Value<bool>,
+    ) -> Result<([AssignedWord<F>; 16], Block<F>), Error> {
+        let mut v = Vec::with_capacity(16);
+        for word in h.iter() {
+            v.push(self.copy_word(region, *offset, word)?);
+            *offset += 1;
+        }
+        for iv in BLAKE2B_IV.iter() {
+            v.push(self.assign_constant_word(region, *offset, *iv)?);
+            *offset += 1;
+        }
+        let mut v: [AssignedWord<F>; 16] = v.try_into().unwrap();
+
+        let t = [
+            self.assign_word(region, *offset, t[0])?,
+            self.assign_word(region, *offset + 1, t[1])?,
+        ];
+        let (f, f_mask) = self.assign_final_block_mask(region, *offset + 2, f)?;
+        *offset += 3;
+
+        let mut message = Vec::with_capacity(16);
+        for word in m.iter() {
+            message.push(self.assign_word(region, *offset, *word)?);
+            *offset += 1;
+        }
+
+        // Mix the 128-bit counter t into v[12..13]
+        for (idx, t) in t.iter().enumerate() {
+            let acc = self.start_chain(region, offset, &v[12 + idx])?;
+            v[12 + idx] = self.xor(region, offset, &acc, t)?;
+        }
+
+        // Invert all the bits of v[14] for the last block
+        let acc = self.start_chain(region, offset, &v[14])?;
+        v[14] = self.xor(region, offset, &acc, &f_mask)?;
+
+        let block = Block {
+            m: message.try_into().unwrap(),
+            t,
+            f,
+        };
+        Ok((v, block))
+    }
+
+    // The eight G calls of a round on v, with the message words x of the
+    // round in the order they take them
+    fn round_in<F: FieldExt>(
+        &self,
+        region: &mut Region<'_, F>,
+        offset: &mut usize,
+        v: &mut [AssignedWord<F>; 16],
+        x: &[AssignedWord<F>; 16],
+    ) -> Result<(), Error> {
+        for (idx, indices) in G_INDICES.iter().enumerate() {
+            self.g(region, offset, v, *indices, &x[2 * idx], &x[2 * idx + 1])?;
+        }
+        Ok(())
+    }
+
+    // The G mixing function on v[a], v[b], v[c], v[d] with message words x
+    // and y, laid out as a single chain. Every step starts from the word the
+    // previous one produced, which works out since addition and XOR commute.
+    // The message words are copied in by the additions that use them.
+    fn g<F: FieldExt>(
+        &self,
+        region: &mut Region<'_, F>,
+        offset: &mut usize,
+        v: &mut [AssignedWord<F>; 16],
+        [a, b, c, d]: [usize; 4],
+        x: &AssignedWord<F>,
+        y: &AssignedWord<F>,
     ) -> Result<(), Error> {
+        let [r1, r2, r3, r4] = BLAKE2B_ROTATIONS;
+
+        let acc = self.start_chain(region, offset, &v[a])?;
+        v[a] = self.add(region, offset, &acc, &[&v[b], x])?;
+        let acc = self.xor(region, offset, &v[a], &v[d])?;
+        v[d] = self.rotate_right(region, offset, &acc, r1)?;
+        v[c] = self.add(region, offset, &v[d], &[&v[c]])?;
+        let acc = self.xor(region, offset, &v[c], &v[b])?;
+        v[b] = self.rotate_right(region, offset, &acc, r2)?;
+
+        v[a] = self.add(region, offset, &v[b], &[&v[a], y])?;
+        let acc = self.xor(region, offset, &v[a], &v[d])?;
+        v[d] = self.rotate_right(region, offset, &acc, r3)?;
+        v[c] = self.add(region, offset, &v[d], &[&v[c]])?;
+        let acc = self.xor(region, offset, &v[c], &v[b])?;
+        v[b] = self.rotate_right(region, offset, &acc, r4)?;
+
+        Ok(())
+    }
+
+    // Assigns a flag for each of the `max_rounds` rounds, set for the first
+    // `rounds` of them, next to the running count of set flags. The count
+    // starts from 0 on a row of its own and ends in `rounds`.
+    fn assign_round_flags<F: FieldExt>(
+        &self,
+        region: &mut Region<'_, F>,
+        offset: &mut usize,
+        rounds: &AssignedWord<F>,
+        max_rounds: usize,
+    ) -> Result<Vec<AssignedCell<F, F>>, Error> {
+        let num_rounds = rounds.value.map(|rounds| rounds as usize);
 
-        layouter.assign_region(|| "blake2b compression",
-         |mut region| {
-            let mut v = [Base::zero(); 16];
-            for (idx, iv) in BLAKE2B_IV.iter().enumerate() {
-                v[idx] = Base::from_u64(*iv);
+        let flag = region.assign_advice(|| "flag", self.carry, *offset, || Value::known(F::one()))?;
+        region.constrain_constant(flag.cell(), F::one())?;
+        let mut count =
+            region.assign_advice(|| "count", self.xor.word, *offset, || Value::known(F::zero()))?;
+        region.constrain_constant(count.cell(), F::zero())?;
+        *offset += 1;
+
+        let mut flags = Vec::with_capacity(max_rounds);
+        for round in 0..max_rounds {
+            self.s_rounds.enable(region, *offset)?;
+            flags.push(region.assign_advice(
+                || format!("flag {}", round),
+                self.carry,
+                *offset,
+                || num_rounds.map(|rounds| F::from((round < rounds) as u64)),
+            )?);
+            count = region.assign_advice(
+                || format!("count {}", round),
+                self.xor.word,
+                *offset,
+                || num_rounds.map(|rounds| F::from(rounds.min(round + 1) as u64)),
+            )?;
+            *offset += 1;
+        }
+        region.constrain_equal(count.cell(), rounds.cell.cell())?;
+
+        Ok(flags)
+    }
+
+    // new if flag is set and old otherwise, on a single row. A word rotated
+    // by rewiring has no cell to copy from, so it gets a row of its own first.
+    fn select<F: FieldExt>(
+        &self,
+        region: &mut Region<'_, F>,
+        offset: &mut usize,
+        flag: &AssignedCell<F, F>,
+        old: &AssignedWord<F>,
+        new: &AssignedWord<F>,
+    ) -> Result<AssignedWord<F>, Error> {
+        assert_eq!(old.rotation, 0, "old words are selected ones");
+        let new = match new.rotation {
+            0 => new.clone(),
+            _ => self.start_chain(region, offset, new)?,
+        };
+
+        self.select_cell(region, offset, flag, &old.cell, &new.cell)
+    }
+
+    // The same for cells holding words, which need not be words of the
+    // compression
+    fn select_cell<F: FieldExt>(
+        &self,
+        region: &mut Region<'_, F>,
+        offset: &mut usize,
+        flag: &AssignedCell<F, F>,
+        old: &AssignedCell<F, F>,
+        new: &AssignedCell<F, F>,
+    ) -> Result<AssignedWord<F>, Error> {
+        self.s_select.enable(region, *offset)?;
+        flag.copy_advice(|| "flag", region, self.carry, *offset)?;
+        old.copy_advice(|| "old", region, self.sub_pieces[0], *offset)?;
+        new.copy_advice(|| "new", region, self.sub_pieces[1], *offset)?;
+
+        let value = flag
+            .value()
+            .zip(old.value().zip(new.value()))
+            .map(|(flag, (old, new))| if *flag == F::one() { new } else { old })
+            .map(|word| word.get_lower_128() as u64);
+        let out = self.assign_word(region, *offset, value)?;
+
+        *offset += 1;
+        Ok(out)
+    }
+
+    // Assigns the final block flag along with the mask it XORs into v[14]
+    fn assign_final_block_mask<F: FieldExt>(
+        &self,
+        region: &mut Region<'_, F>,
+        offset: usize,
+        f: Value<bool>,
+    ) -> Result<(AssignedCell<F, F>, AssignedWord<F>), Error> {
+        self.s_final.enable(region, offset)?;
+        let flag = region.assign_advice(|| "f", self.carry, offset, || f.map(|f| F::from(f as u64)))?;
+        let mask = self.assign_word(region, offset, f.map(|f| if f { u64::MAX } else { 0 }))?;
+        Ok((flag, mask))
+    }
+
+    // Copies `word` onto the next row, to be used as the first operand of a chain
+    fn start_chain<F: FieldExt>(
+        &self,
+        region: &mut Region<'_, F>,
+        offset: &mut usize,
+        word: &AssignedWord<F>,
+    ) -> Result<AssignedWord<F>, Error> {
+        let acc = self.copy_word(region, *offset, word)?;
+        *offset += 1;
+        Ok(acc)
+    }
+
+    /// Adds one or two words to `acc` modulo 2^64, where `acc` is the word on
+    /// the row right above `offset`: either the start of a chain or the result
+    /// of the previous operation, possibly rotated by a whole number of chunks.
+    /// The operands are copied in and range-checked by their decomposition,
+    /// and the carry (at most 2) is witnessed next to the sum. The sum is
+    /// returned already decomposed, on the last row of the operation, so the
+    /// next operation of the chain can read it in place.
+    pub(crate) fn add<F: FieldExt>(
+        &self,
+        region: &mut Region<'_, F>,
+        offset: &mut usize,
+        acc: &AssignedWord<F>,
+        operands: &[&AssignedWord<F>],
+    ) -> Result<AssignedWord<F>, Error> {
+        let rotation = ACC_ROTATIONS
+            .iter()
+            .position(|r| *r == acc.rotation)
+            .unwrap_or_else(|| panic!("cannot add a word rotated by {}", acc.rotation));
+        let s_add = match operands.len() {
+            1 => self.s_add2[rotation],
+            2 => self.s_add3[rotation],
+            _ => panic!("can only add two or three words"),
+        };
+        s_add.enable(region, *offset - 1)?;
+        for (idx, operand) in operands.iter().enumerate() {
+            self.copy_word(region, *offset + idx, operand)?;
+        }
+        *offset += operands.len();
+
+        let words = std::iter::once(acc)
+            .chain(operands.iter().copied())
+            .map(|word| word.value)
+            .collect();
+        let (sum, carry) = sum_with_carry(words);
+        region.assign_advice(|| "carry", self.carry, *offset, || carry.map(F::from))?;
+        let sum = self.assign_word(region, *offset, sum)?;
+
+        *offset += 1;
+        Ok(sum)
+    }
+
+    // acc ^ rhs, where acc is the word on the row right above `offset`
+    fn xor<F: FieldExt>(
+        &self,
+        region: &mut Region<'_, F>,
+        offset: &mut usize,
+        acc: &AssignedWord<F>,
+        rhs: &AssignedWord<F>,
+    ) -> Result<AssignedWord<F>, Error> {
+        assert_eq!(acc.rotation, 0, "xor reads acc in place");
+        self.copy_word(region, *offset, rhs)?;
+
+        let value = acc.value.zip(rhs.value).map(|(acc, rhs)| acc ^ rhs);
+        let word = self.xor.assign_xor(region, *offset - 1, acc.value, rhs.value)?;
+
+        *offset += 3;
+        Ok(AssignedWord::new(word, value))
+    }
+
+    // Feed forward: h'[i] = h[i] ^ v[i] ^ v[i + 8], each as a single
+    // three-input XOR. Returns the output words h'[0..7].
+    fn feed_forward<F: FieldExt>(
+        &self,
+        region: &mut Region<'_, F>,
+        offset: &mut usize,
+        h: &[AssignedWord<F>; STATE],
+        v: &[AssignedWord<F>; 16],
+    ) -> Result<[AssignedWord<F>; STATE], Error> {
+        let mut words = Vec::with_capacity(STATE);
+        for (idx, h) in h.iter().enumerate() {
+            let acc = self.start_chain(region, offset, h)?;
+            words.push(self.xor3(region, offset, &acc, &v[idx], &v[idx + 8])?);
+        }
+        Ok(words.try_into().unwrap())
+    }
+
+    // acc ^ lhs ^ rhs, where acc is the word on the row right above `offset`
+    fn xor3<F: FieldExt>(
+        &self,
+        region: &mut Region<'_, F>,
+        offset: &mut usize,
+        acc: &AssignedWord<F>,
+        lhs: &AssignedWord<F>,
+        rhs: &AssignedWord<F>,
+    ) -> Result<AssignedWord<F>, Error> {
+        assert_eq!(acc.rotation, 0, "xor reads acc in place");
+        self.copy_word(region, *offset, lhs)?;
+        self.copy_word(region, *offset + 1, rhs)?;
+
+        let values = [acc.value, lhs.value, rhs.value];
+        let value = acc.value.zip(lhs.value).zip(rhs.value).map(|((acc, lhs), rhs)| acc ^ lhs ^ rhs);
+        let word = self.xor.assign_xor3(region, *offset - 1, values)?;
+
+        *offset += 4;
+        Ok(AssignedWord::new(word, value))
+    }
+
+    // acc >>> n, where n is one of BLAKE2B_ROTATIONS. Rotations by a whole
+    // number of chunks only reorder the chunks of acc and take no rows. The
+    // rotation by 63 reads acc in place from the row right above `offset`,
+    // and splits its top chunk into sub-pieces on the same row.
+    fn rotate_right<F: FieldExt>(
+        &self,
+        region: &mut Region<'_, F>,
+        offset: &mut usize,
+        acc: &AssignedWord<F>,
+        n: usize,
+    ) -> Result<AssignedWord<F>, Error> {
+        if n % CHUNK_SIZE == 0 {
+            let mut chunks = acc.chunks.clone();
+            chunks.rotate_left(n / CHUNK_SIZE);
+            let rotated = AssignedWord {
+                cell: acc.cell.clone(),
+                chunks,
+                rotation: (acc.rotation + n) % 64,
+                value: acc.value.map(|acc| acc.rotate_right(n as u32)),
+            };
+            if self.byte_rotations_laid_out {
+                return self.start_chain(region, offset, &rotated);
             }
-    
-            // Assign the initial values of v to the advice cells
-            for (idx, value) in v.iter().enumerate() {
-                region.assign_advice(|| format!("v_{}", idx), self.advice[idx], 0, || Ok(*value))?;
+            return Ok(rotated);
+        }
+
+        assert_eq!(n, ROTR_63.rotation, "unsupported rotation {}", n);
+        assert_eq!(acc.rotation, 0, "the rotation by 63 reads acc in place");
+        let sub_pieces = acc
+            .value
+            .map(|acc| ROTR_63.split_into(acc).into_iter().map(F::from).collect());
+        let out = acc.value.map(|acc| ROTR_63.combine(acc));
+        self.assign_rotr63(region, offset, sub_pieces, out)
+    }
+
+    // Assigns the sub-pieces of the word on the row right above `offset` and
+    // its rotation by 63 on `offset`, as witnessed by the caller
+    fn assign_rotr63<F: FieldExt>(
+        &self,
+        region: &mut Region<'_, F>,
+        offset: &mut usize,
+        sub_pieces: Value<Vec<F>>,
+        out: Value<u64>,
+    ) -> Result<AssignedWord<F>, Error> {
+        self.s_rotr63.enable(region, *offset - 1)?;
+        for (idx, column) in self.sub_pieces.iter().enumerate() {
+            region.assign_advice(
+                || format!("sub-piece {}", idx),
+                *column,
+                *offset - 1,
+                || sub_pieces.as_ref().map(|pieces| pieces.get(idx).copied().unwrap_or(F::zero())),
+            )?;
+        }
+
+        let out = self.assign_word(region, *offset, out)?;
+        *offset += 1;
+        Ok(out)
+    }
+
+    // Assigns a word on the given row, decomposed into spread chunks
+    fn assign_word<F: FieldExt>(
+        &self,
+        region: &mut Region<'_, F>,
+        offset: usize,
+        value: Value<u64>,
+    ) -> Result<AssignedWord<F>, Error> {
+        let word = self.xor.assign_word(region, offset, value)?;
+        Ok(AssignedWord::new(word, value))
+    }
+
+    // Copies a previously assigned word onto the given row and decomposes it
+    // again. A word rotated by rewiring is copied chunk by chunk.
+    fn copy_word<F: FieldExt>(
+        &self,
+        region: &mut Region<'_, F>,
+        offset: usize,
+        word: &AssignedWord<F>,
+    ) -> Result<AssignedWord<F>, Error> {
+        let copy = self.assign_word(region, offset, word.value)?;
+        if word.rotation == 0 {
+            region.constrain_equal(word.cell.cell(), copy.cell.cell())?;
+        } else {
+            for (chunk, copy) in word.chunks.iter().zip(copy.chunks.iter()) {
+                region.constrain_equal(chunk.cell(), copy.cell())?;
+            }
+        }
+        Ok(copy)
+    }
+
+    fn assign_constant_word<F: FieldExt>(
+        &self,
+        region: &mut Region<'_, F>,
+        offset: usize,
+        constant: u64,
+    ) -> Result<AssignedWord<F>, Error> {
+        let word = self.assign_word(region, offset, Value::known(constant))?;
+        region.constrain_constant(word.cell.cell(), F::from(constant))?;
+        Ok(word)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use halo2_proofs::{
+        circuit::SimpleFloorPlanner,
+        dev::{MockProver, VerifyFailure},
+        halo2curves::bn256::Fr,
+        plonk::Circuit,
+    };
+
+    // Adds its operands in a single chain and checks the sum against the native one
+    struct AddCircuit {
+        operands: Vec<u64>,
+    }
+
+    impl Circuit<Fr> for AddCircuit {
+        type Config = CompressionConfig;
+        type FloorPlanner = SimpleFloorPlanner;
+
+        fn without_witnesses(&self) -> Self {
+            Self {
+                operands: self.operands.clone(),
             }
-    
-            // Iterate through the rounds
-            for round in 0..12 {
-                let sigma = &BLAKE2B_SIGMA[round];
-                self.selector.enable(&mut region, round)?;
-    
-                for idx in 0..8 {
-                    let a = 2 * idx;
-                    let b = 2 * idx + 1;
-                    let c = (2 * idx + 2) % 16;
-                    let d = (2 * idx + 3) % 16;
-    
-                    let x = Base::from_u64(sigma[a] as u64);
-                    let y = Base::from_u64(sigma[b] as u64);
-    
-                    let expressions: [Expression<Base>; 16] = (0..16)
-                        .map(|i| Expression::from(region.get_advice(self.advice[i], round)))
-                        .collect::<Vec<_>>()
-                        .try_into()
-                        .unwrap();
-    
-                    self.blake2_g(&mut region, &mut expressions.clone(), a, b, c, d, Expression::Constant(x), Expression::Constant(y))?;
-    
-                    for (idx, expression) in expressions.iter().enumerate() {
-                        region.assign_advice(|| format!("v_{}", idx), self.advice[idx], round + 1, || {
-                            Ok(expression.evaluate(&|_| { Base::zero() }))
-                        })?;
+        }
+
+        fn configure(meta: &mut ConstraintSystem<Fr>) -> Self::Config {
+            CompressionConfig::configure(meta)
+        }
+
+        fn synthesize(&self, config: Self::Config, mut layouter: impl Layouter<Fr>) -> Result<(), Error> {
+            config.load(&mut layouter)?;
+            let expected = self.operands.iter().fold(0u64, |acc, word| acc.wrapping_add(*word));
+
+            layouter.assign_region(
+                || "add",
+                |mut region| {
+                    let words = self
+                        .operands
+                        .iter()
+                        .enumerate()
+                        .map(|(offset, word)| config.assign_word(&mut region, offset, Value::known(*word)))
+                        .collect::<Result<Vec<_>, _>>()?;
+
+                    let mut offset = words.len();
+                    let acc = config.start_chain(&mut region, &mut offset, &words[0])?;
+                    let operands = words[1..].iter().collect::<Vec<_>>();
+                    let sum = config.add(&mut region, &mut offset, &acc, &operands)?;
+                    region.constrain_constant(sum.cell.cell(), Fr::from(expected))
+                },
+            )
+        }
+    }
+
+    // Rotates a word right by every BLAKE2b rotation and checks the results
+    // against the native ones
+    struct RotateCircuit {
+        word: u64,
+    }
+
+    impl Circuit<Fr> for RotateCircuit {
+        type Config = CompressionConfig;
+        type FloorPlanner = SimpleFloorPlanner;
+
+        fn without_witnesses(&self) -> Self {
+            Self { word: self.word }
+        }
+
+        fn configure(meta: &mut ConstraintSystem<Fr>) -> Self::Config {
+            CompressionConfig::configure(meta)
+        }
+
+        fn synthesize(&self, config: Self::Config, mut layouter: impl Layouter<Fr>) -> Result<(), Error> {
+            config.load(&mut layouter)?;
+
+            layouter.assign_region(
+                || "rotate",
+                |mut region| {
+                    let word = config.assign_word(&mut region, 0, Value::known(self.word))?;
+                    let mut offset = 1;
+                    for n in BLAKE2B_ROTATIONS {
+                        let acc = config.start_chain(&mut region, &mut offset, &word)?;
+                        let out = config.rotate_right(&mut region, &mut offset, &acc, n)?;
+                        let out = config.start_chain(&mut region, &mut offset, &out)?;
+                        region.constrain_constant(
+                            out.cell.cell(),
+                            Fr::from(self.word.rotate_right(n as u32)),
+                        )?;
                     }
-                }
+                    Ok(())
+                },
+            )
+        }
+    }
+
+    // Rotates a word right by 63 with the sub-pieces and output given, and
+    // fixes the output to them
+    struct Rotr63Circuit {
+        word: u64,
+        sub_pieces: [Fr; MAX_SUB_PIECES],
+        out: u64,
+    }
+
+    impl Circuit<Fr> for Rotr63Circuit {
+        type Config = CompressionConfig;
+        type FloorPlanner = SimpleFloorPlanner;
+
+        fn without_witnesses(&self) -> Self {
+            Self {
+                word: self.word,
+                sub_pieces: self.sub_pieces,
+                out: self.out,
             }
+        }
+
+        fn configure(meta: &mut ConstraintSystem<Fr>) -> Self::Config {
+            CompressionConfig::configure(meta)
+        }
+
+        fn synthesize(&self, config: Self::Config, mut layouter: impl Layouter<Fr>) -> Result<(), Error> {
+            config.load(&mut layouter)?;
+
+            layouter.assign_region(
+                || "rotr 63",
+                |mut region| {
+                    let word = config.assign_word(&mut region, 0, Value::known(self.word))?;
+                    let mut offset = 1;
+                    config.start_chain(&mut region, &mut offset, &word)?;
+                    let (sub_pieces, out) = (Value::known(self.sub_pieces.to_vec()), Value::known(self.out));
+                    let out = config.assign_rotr63(&mut region, &mut offset, sub_pieces, out)?;
+                    region.constrain_constant(out.cell.cell(), Fr::from(self.out))
+                },
+            )
+        }
+    }
+
+    // Reads a number of rounds from the instance column, decomposed into
+    // its bytes, or into the chunks `dense` a prover could pick instead
+    #[derive(Default)]
+    struct RoundsCircuit {
+        dense: Option<[u64; NUM_CHUNKS]>,
+    }
+
+    impl Circuit<Fr> for RoundsCircuit {
+        type Config = (CompressionConfig, Column<Instance>);
+        type FloorPlanner = SimpleFloorPlanner;
+
+        fn without_witnesses(&self) -> Self {
+            Self { dense: self.dense }
+        }
+
+        fn configure(meta: &mut ConstraintSystem<Fr>) -> Self::Config {
+            let instance = meta.instance_column();
+            meta.enable_equality(instance);
+            (CompressionConfig::configure(meta), instance)
+        }
+
+        fn synthesize(
+            &self,
+            (config, instance): Self::Config,
+            mut layouter: impl Layouter<Fr>,
+        ) -> Result<(), Error> {
+            config.load(&mut layouter)?;
+            match self.dense {
+                None => config.assign_rounds(&mut layouter, instance, 0)?,
+                Some(dense) => layouter.assign_region(
+                    || "rounds",
+                    |mut region| {
+                        let rounds =
+                            region.assign_advice_from_instance(|| "rounds", instance, 0, config.carry, 0)?;
+                        config.decompose_rounds(&mut region, &rounds, Value::known(dense))
+                    },
+                )?,
+            };
             Ok(())
-        })
+        }
     }
-    
 
-}
+    fn lookup_failures(failures: &[VerifyFailure]) -> bool {
+        !failures.is_empty() && failures.iter().all(|failure| matches!(failure, VerifyFailure::Lookup { .. }))
+    }
+
+    // Witnesses a final block flag next to the mask it XORs into v[14]
+    struct FinalBlockCircuit {
+        f: Fr,
+        mask: u64,
+    }
+
+    impl Circuit<Fr> for FinalBlockCircuit {
+        type Config = CompressionConfig;
+        type FloorPlanner = SimpleFloorPlanner;
+
+        fn without_witnesses(&self) -> Self {
+            Self {
+                f: self.f,
+                mask: self.mask,
+            }
+        }
+
+        fn configure(meta: &mut ConstraintSystem<Fr>) -> Self::Config {
+            CompressionConfig::configure(meta)
+        }
+
+        fn synthesize(&self, config: Self::Config, mut layouter: impl Layouter<Fr>) -> Result<(), Error> {
+            config.load(&mut layouter)?;
 
+            layouter.assign_region(
+                || "final block",
+                |mut region| {
+                    config.s_final.enable(&mut region, 0)?;
+                    region.assign_advice(|| "f", config.carry, 0, || Value::known(self.f))?;
+                    config.assign_word(&mut region, 0, Value::known(self.mask))?;
+                    Ok(())
+                },
+            )
+        }
+    }
+
+    #[test]
+    fn test_rounds_range() {
+        let verify = |circuit: RoundsCircuit, rounds: Fr| {
+            let prover = MockProver::run(10, &circuit, vec![vec![rounds]]).unwrap();
+            prover.verify()
+        };
+        // The largest number of rounds EIP-152 encodes
+        assert_eq!(verify(RoundsCircuit::default(), Fr::from(u32::MAX as u64)), Ok(()));
+
+        // 2^32 has its only decomposition into bytes outside the low four
+        // chunks, and the one that fits in them has a chunk of 256, as does
+        // that of 2^64 = 2^40 * 2^24
+        let two_pow_64 = Fr::from(u64::MAX) + Fr::from(1);
+        assert!(verify(RoundsCircuit::default(), Fr::from(1 << 32)).is_err());
+        for (rounds, top) in [(Fr::from(1 << 32), 1 << 8), (two_pow_64, 1 << 40)] {
+            let circuit = RoundsCircuit {
+                dense: Some([0, 0, 0, top, 0, 0, 0, 0]),
+            };
+            assert!(lookup_failures(&verify(circuit, rounds).unwrap_err()));
+        }
+    }
 
+    #[test]
+    fn test_final_block_flag_boolean() {
+        for (f, mask, valid) in [(0, 0, true), (1, u64::MAX, true), (2, u64::MAX, false), (2, 0, false)] {
+            let circuit = FinalBlockCircuit { f: Fr::from(f), mask };
+            let prover = MockProver::run(10, &circuit, vec![]).unwrap();
+            assert_eq!(prover.verify().is_ok(), valid, "f = {}, mask = {:x}", f, mask);
+        }
+    }
 
+    #[test]
+    fn test_rotations() {
+        for word in [0x0123456789abcdef, 0xfedcba9876543210] {
+            let circuit = RotateCircuit { word };
+            let prover = MockProver::run(10, &circuit, vec![]).unwrap();
+            assert_eq!(prover.verify(), Ok(()));
+        }
+    }
+
+    #[test]
+    fn test_rotr63_tampered() {
+        let verify = |word: u64, sub_pieces: [Fr; MAX_SUB_PIECES], out: u64| {
+            let circuit = Rotr63Circuit { word, sub_pieces, out };
+            MockProver::run(10, &circuit, vec![]).unwrap().verify()
+        };
+        // The top chunk of 0x80.. splits into a 7-bit piece 0 and a 1-bit 1
+        let word = 0x8000_0000_0000_0001;
+        assert_eq!(verify(word, [Fr::from(0), Fr::from(1)], word.rotate_right(63)), Ok(()));
+
+        // An output other than the one the pieces recompose to
+        let out = word.rotate_right(63) ^ 1;
+        assert!(verify(word, [Fr::from(0), Fr::from(1)], out).is_err());
+
+        // Pieces 128 and -1 of the top chunk of 0 still add up to it, and
+        // recompose to 2^64 - 1, so only their range checks reject them
+        let failures = verify(0, [Fr::from(128), -Fr::from(1)], u64::MAX).unwrap_err();
+        assert!(lookup_failures(&failures));
+    }
+
+    #[test]
+    fn test_add_two_words_wraps_around() {
+        let circuit = AddCircuit {
+            operands: vec![u64::MAX, 2],
+        };
+        let prover = MockProver::run(10, &circuit, vec![]).unwrap();
+        assert_eq!(prover.verify(), Ok(()));
+    }
+
+    #[test]
+    fn test_add_three_words_with_carry_two() {
+        let circuit = AddCircuit {
+            operands: vec![u64::MAX, u64::MAX, u64::MAX],
+        };
+        let prover = MockProver::run(10, &circuit, vec![]).unwrap();
+        assert_eq!(prover.verify(), Ok(()));
+    }
+}
@@ -1,63 +1,130 @@
 use std::marker::PhantomData;
-use std::ops::BitXor;
 
 use halo2_proofs::arithmetic::FieldExt;
 use halo2_proofs::plonk::Expression;
 
-//use super::super::utils::*;
-use super::super::compression::{StateChunk, MessageChunk};
-
-
-//mod bit_chunk;
-
-use crate::compression::bit_chunk::*;
+use super::bit_chunk::BitChunk;
+use crate::xor_chunks::CHUNK_SIZE;
 
 pub struct CompressionGate<F>(PhantomData<F>);
 
-pub trait FieldElement {
-    fn bitxor(&self, other: &Self) -> Self;
-}
-
 impl<F: FieldExt> CompressionGate<F> {
 
     fn ones() -> Expression<F> {
         Expression::Constant(F::one())
     }
 
-    // Implement G function
-    pub fn g_func<BitChunkSpread>(v: [Expression<F>; 16], a: usize, b: usize, c: usize, d: usize, x: Expression<F>, y: Expression<F>) -> Vec<F> {
-        let w = 64; // Word size
-        // are r1 constant?
-        let r1 = 32;
-        let r2 = 24;
-        let r3 = 16;
-        let r4 = 64;
-
-        let tmp1 = v[a] + v[b] + x;
-        let tmp2 = v[d] ^ tmp1;
-        let tmp3 = v[c] + tmp2;
-        let tmp4 = v[b] ^ tmp3;
-        let tmp5 = v[a] + tmp4 + y;
-        let tmp6 = v[d] ^ tmp5;
-        let tmp7 = v[c] + tmp6;
-        let tmp8 = v[b] ^ tmp7;
-
-         // TODO: replace rotate_right with >>> operators
-         // I dont believe that operator is in rust? I could be wrong, also we may want to deprioritize this if this is for optimization
-        fn rotate_right(x: u64, n: u32) -> u64 {
-            (x >> n) | (x << (64 - n))
-        }
+    fn two_pow_64() -> Expression<F> {
+        Expression::Constant(F::from_u128(1 << 64))
+    }
+
+    fn pow_2(exponent: usize) -> Expression<F> {
+        Expression::Constant(F::from_u128(1 << exponent))
+    }
+
+    // The word whose chunks, least significant first, are `chunks` rotated
+    // right by `rotation` bits, a whole number of chunks
+    pub fn rotated_word(chunks: &[Expression<F>], rotation: usize) -> Expression<F> {
+        assert_eq!(rotation % CHUNK_SIZE, 0);
+        let shift = rotation / CHUNK_SIZE;
+        (0..chunks.len()).rev().fold(Expression::Constant(F::zero()), |acc, idx| {
+            acc * Self::pow_2(CHUNK_SIZE) + chunks[(idx + shift) % chunks.len()].clone()
+        })
+    }
 
-        v[a] = tmp1;
-        v[d] = tmp2.rotate_right(r1);
-        v[c] = tmp3;
-        v[b] = tmp4.rotate_right(r2);
-        v[a] = tmp5;
-        v[d] = tmp6.rotate_right(r3);
-        v[c] = tmp7;
-        v[b] = tmp8.rotate_right(r4);
+    // sum(operands) = sum + carry * 2^64, where the carry is at most
+    // operands.len() - 1 since every operand is below 2^64
+    pub fn s_add(
+        s_add: Expression<F>,
+        operands: &[Expression<F>],
+        sum: Expression<F>,
+        carry: Expression<F>,
+    ) -> Vec<Expression<F>> {
+        let total = operands
+            .iter()
+            .fold(Expression::Constant(F::zero()), |acc, operand| acc + operand.clone());
+        let carry_range = (0..operands.len() as u64).fold(Self::ones(), |acc, value| {
+            acc * (carry.clone() - Expression::Constant(F::from(value)))
+        });
 
-        v.iter().cloned().collect()
+        vec![
+            s_add.clone() * (total - sum - carry * Self::two_pow_64()),
+            s_add * carry_range,
+        ]
+    }
+
+    // flag is boolean and only set if flag_prev is, and count = count_prev + flag
+    pub fn s_rounds(
+        s_rounds: Expression<F>,
+        flag_prev: Expression<F>,
+        flag: Expression<F>,
+        count_prev: Expression<F>,
+        count: Expression<F>,
+    ) -> Vec<Expression<F>> {
+        vec![
+            s_rounds.clone() * flag.clone() * (Self::ones() - flag.clone()),
+            s_rounds.clone() * flag.clone() * (Self::ones() - flag_prev),
+            s_rounds * (count_prev + flag - count),
+        ]
+    }
+
+    // f is boolean and mask = f * (2^64 - 1), so that XORing the mask into a
+    // word inverts all of its bits exactly when f is set
+    pub fn s_final(s_final: Expression<F>, f: Expression<F>, mask: Expression<F>) -> Vec<Expression<F>> {
+        vec![
+            s_final.clone() * f.clone() * (Self::ones() - f.clone()),
+            s_final * (mask - f * Expression::Constant(F::from(u64::MAX))),
+        ]
+    }
+
+    // out = new if flag is set, old otherwise, for a boolean flag
+    pub fn s_select(
+        s_select: Expression<F>,
+        flag: Expression<F>,
+        old: Expression<F>,
+        new: Expression<F>,
+        out: Expression<F>,
+    ) -> Vec<Expression<F>> {
+        vec![s_select * (old.clone() + flag * (new - old) - out)]
+    }
+
+    // out = word >>> n, for the split of the word into pieces chosen for n.
+    // Pieces that are whole chunks of the word are read from its chunk
+    // columns, the others from the sub-piece columns, in which case their
+    // chunk has to be their sum.
+    pub fn s_rotr(
+        s_rotr: Expression<F>,
+        chunks: &[Expression<F>],
+        sub_pieces: &[Expression<F>],
+        out: Expression<F>,
+        split: BitChunk,
+    ) -> Vec<Expression<F>> {
+        let mut sub_pieces = sub_pieces.iter();
+        let mut chunk_sums = vec![None; chunks.len()];
+        let mut rotated = Expression::Constant(F::zero());
+
+        for (offset, width) in split.pieces() {
+            let piece = if BitChunk::is_chunk(offset, width) {
+                chunks[offset / CHUNK_SIZE].clone()
+            } else {
+                let piece = sub_pieces.next().expect("enough sub-piece columns").clone();
+                let shifted = piece.clone() * Self::pow_2(offset % CHUNK_SIZE);
+                let sum = &mut chunk_sums[offset / CHUNK_SIZE];
+                *sum = Some(match sum.take() {
+                    Some(sum) => sum + shifted,
+                    None => shifted,
+                });
+                piece
+            };
+            rotated = rotated + piece * Self::pow_2(split.rotated_offset(offset));
+        }
 
+        chunk_sums
+            .into_iter()
+            .zip(chunks.iter())
+            .filter_map(|(sum, chunk)| sum.map(|sum| chunk.clone() - sum))
+            .chain(std::iter::once(rotated - out))
+            .map(|poly| s_rotr.clone() * poly)
+            .collect()
     }
 }
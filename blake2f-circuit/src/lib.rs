@@ -1,5 +1,7 @@
 pub mod compression;
+pub mod blake2b;
 pub mod blake2f;
+pub mod trace;
 pub mod utils;
 pub mod xor_chunks;
 //pub mod cgpt_compression;
\ No newline at end of file
@@ -0,0 +1,278 @@
+//! Extracts the calls to the BLAKE2 F precompile from execution traces in
+//! the style of geth's `debug_traceTransaction` with the `callTracer`, so
+//! they can be proven with `Blake2fTestCircuit`.
+//!
+//! A trace file holds either a single call frame, a JSON-RPC response whose
+//! `result` is one, or an array of those, as `debug_traceBlockByNumber`
+//! returns them.
+
+use std::path::Path;
+
+use ethers_core::types::{Address, Bytes, H512};
+use serde::Deserialize;
+
+use crate::blake2f::Blake2fWitness;
+
+/// The address of the BLAKE2 F precompile.
+pub const BLAKE2F_ADDRESS: u64 = 0x09;
+
+/// A call frame of the `callTracer`, keeping only the fields needed to find
+/// the calls to the precompile.
+#[derive(Clone, Debug, Deserialize)]
+struct CallFrame {
+    #[serde(rename = "type")]
+    kind: String,
+    to: Option<Address>,
+    #[serde(default)]
+    input: Bytes,
+    output: Option<Bytes>,
+    error: Option<String>,
+    #[serde(default)]
+    calls: Vec<CallFrame>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+enum TraceFile {
+    Frame(CallFrame),
+    Response { result: Box<TraceFile> },
+    Traces(Vec<TraceFile>),
+}
+
+/// A trace that the calls to the precompile could not be extracted from.
+#[derive(Debug)]
+pub enum TraceError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    /// A successful call to the precompile returned this many bytes instead
+    /// of 64.
+    InvalidOutputLength(usize),
+}
+
+impl std::fmt::Display for TraceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "cannot read trace: {}", err),
+            Self::Json(err) => write!(f, "cannot parse trace: {}", err),
+            Self::InvalidOutputLength(length) => {
+                write!(f, "precompile output is {} bytes long instead of 64", length)
+            }
+        }
+    }
+}
+
+impl std::error::Error for TraceError {}
+
+impl From<std::io::Error> for TraceError {
+    fn from(err: std::io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for TraceError {
+    fn from(err: serde_json::Error) -> Self {
+        Self::Json(err)
+    }
+}
+
+/// Reads the trace file at `path` and returns the inputs and outputs of its
+/// calls to the precompile, as `Blake2fTestCircuit` takes them.
+pub fn load_blake2f_calls(path: impl AsRef<Path>) -> Result<(Vec<Blake2fWitness>, Vec<H512>), TraceError> {
+    let trace = std::fs::read_to_string(path)?;
+    blake2f_calls(&trace)
+}
+
+/// Returns the inputs and outputs of the frames to the precompile in a JSON
+/// trace, in the order they were made. A precompile runs the same whatever
+/// the context it is called in, so DELEGATECALL and CALLCODE frames count as
+/// much as CALL and STATICCALL ones. Calls that failed, because EIP-152 rejected their input or they ran out of gas,
+/// are left out, as they have no output to prove.
+pub fn blake2f_calls(trace: &str) -> Result<(Vec<Blake2fWitness>, Vec<H512>), TraceError> {
+    let trace: TraceFile = serde_json::from_str(trace)?;
+    let mut frames = Vec::new();
+    trace.collect_frames(&mut frames);
+
+    let precompile = Address::from_low_u64_be(BLAKE2F_ADDRESS);
+    let mut inputs = Vec::new();
+    let mut outputs = Vec::new();
+    for frame in frames {
+        if !matches!(
+            frame.kind.as_str(),
+            "CALL" | "STATICCALL" | "DELEGATECALL" | "CALLCODE"
+        )
+            || frame.to != Some(precompile)
+            || frame.error.is_some()
+        {
+            continue;
+        }
+        let (input, output) = match (Blake2fWitness::from_eip152_input(&frame.input), &frame.output) {
+            (Ok(input), Some(output)) => (input, output),
+            _ => continue,
+        };
+        if output.len() != 64 {
+            return Err(TraceError::InvalidOutputLength(output.len()));
+        }
+        inputs.push(input);
+        outputs.push(H512::from_slice(output));
+    }
+    Ok((inputs, outputs))
+}
+
+impl TraceFile {
+    // Collects the frames of the trace depth-first, each before its subcalls
+    fn collect_frames<'a>(&'a self, frames: &mut Vec<&'a CallFrame>) {
+        match self {
+            Self::Frame(frame) => frame.collect_frames(frames),
+            Self::Response { result } => result.collect_frames(frames),
+            Self::Traces(traces) => {
+                for trace in traces {
+                    trace.collect_frames(frames);
+                }
+            }
+        }
+    }
+}
+
+impl CallFrame {
+    fn collect_frames<'a>(&'a self, frames: &mut Vec<&'a CallFrame>) {
+        frames.push(self);
+        for call in &self.calls {
+            call.collect_frames(frames);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers_core::{types::H512, utils::hex};
+    use halo2_proofs::{dev::MockProver, halo2curves::bn256::Fr};
+    use std::{
+        marker::PhantomData,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    use super::{blake2f_calls, load_blake2f_calls, TraceError};
+    use crate::blake2f::dev::{Blake2fTestCircuit, EIP152_VECTORS};
+
+    fn call(kind: &str, to: &str, input: &[u8], output: Option<&[u8]>, calls: &[String]) -> String {
+        let output = match output {
+            Some(output) => format!(r#""output": "0x{}","#, hex::encode(output)),
+            None => r#""error": "precompile failed","#.to_string(),
+        };
+        format!(
+            r#"{{"type": "{}", "from": "0x00000000000000000000000000000000000000aa", "to": "{}", "input": "0x{}", {} "calls": [{}]}}"#,
+            kind,
+            to,
+            hex::encode(input),
+            output,
+            calls.join(", ")
+        )
+    }
+
+    const BLAKE2F: &str = "0x0000000000000000000000000000000000000009";
+
+    // A transaction whose contract calls the precompile with vector 7, then
+    // with the malformed vector 3, then with vector 4 from a subcall, around
+    // a call to the SHA-256 precompile.
+    fn trace() -> String {
+        let vector = |idx: usize| {
+            let vector = &EIP152_VECTORS[idx];
+            (vector.input.clone(), vector.output.as_ref().ok().map(|output| output.as_bytes().to_vec()))
+        };
+        let (input_7, output_7) = vector(7);
+        let (input_3, _) = vector(3);
+        let (input_4, output_4) = vector(4);
+
+        let inner = call("STATICCALL", BLAKE2F, &input_4, output_4.as_deref(), &[]);
+        let calls = [
+            call("STATICCALL", BLAKE2F, &input_7, output_7.as_deref(), &[]),
+            call("STATICCALL", BLAKE2F, &input_3, None, &[]),
+            call(
+                "STATICCALL",
+                "0x0000000000000000000000000000000000000002",
+                b"abc",
+                Some(&[0; 32][..]),
+                &[],
+            ),
+            call(
+                "CALL",
+                "0x00000000000000000000000000000000000000bb",
+                &[],
+                Some(&[][..]),
+                &[inner],
+            ),
+        ];
+        let root = call("CALL", "0x00000000000000000000000000000000000000cc", &[], Some(&[][..]), &calls);
+        format!(r#"{{"jsonrpc": "2.0", "id": 1, "result": {}}}"#, root)
+    }
+
+    fn expected_outputs() -> Vec<H512> {
+        [7, 4]
+            .into_iter()
+            .map(|idx| *EIP152_VECTORS[idx].output.as_ref().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_trace_blake2f_calls() {
+        let (inputs, outputs) = blake2f_calls(&trace()).unwrap();
+        let rounds: Vec<_> = inputs.iter().map(|input| input.rounds).collect();
+        assert_eq!(rounds, vec![1, 0]);
+        assert_eq!(outputs, expected_outputs());
+    }
+
+    #[test]
+    fn test_trace_blake2f_calls_block() {
+        let traces = format!(r#"[{{"result": {}}}, {{"result": {}}}]"#, trace(), trace());
+        let (inputs, outputs) = blake2f_calls(&traces).unwrap();
+        assert_eq!(inputs.len(), 4);
+        assert_eq!(outputs, [expected_outputs(), expected_outputs()].concat());
+    }
+
+    #[test]
+    fn test_trace_blake2f_calls_kinds() {
+        let vector = &EIP152_VECTORS[7];
+        let output = vector.output.as_ref().unwrap();
+        let calls: Vec<_> = ["CALL", "STATICCALL", "DELEGATECALL", "CALLCODE"]
+            .into_iter()
+            .map(|kind| call(kind, BLAKE2F, &vector.input, Some(output.as_bytes()), &[]))
+            .collect();
+        let root = call("CALL", "0x00000000000000000000000000000000000000cc", &[], Some(&[][..]), &calls);
+        let (inputs, outputs) = blake2f_calls(&root).unwrap();
+        assert_eq!(inputs.len(), 4);
+        assert_eq!(outputs, vec![*output; 4]);
+    }
+
+    #[test]
+    fn test_trace_invalid_output_length() {
+        let input = &EIP152_VECTORS[7].input;
+        let trace = call("STATICCALL", BLAKE2F, input, Some(&[0; 32][..]), &[]);
+        assert!(matches!(
+            blake2f_calls(&trace),
+            Err(TraceError::InvalidOutputLength(32))
+        ));
+    }
+
+    #[test]
+    fn test_trace_file_circuit() {
+        // A path of its own, so that concurrent test runs do not race on it
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "blake2f-circuit-trace-{}-{}.json",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::write(&path, trace()).unwrap();
+        let calls = load_blake2f_calls(&path);
+        std::fs::remove_file(&path).unwrap();
+        let (inputs, outputs) = calls.unwrap();
+
+        let circuit = Blake2fTestCircuit::<Fr> {
+            inputs,
+            outputs,
+            _marker: PhantomData,
+        };
+        let prover = MockProver::run(12, &circuit, circuit.instance()).unwrap();
+        prover.assert_satisfied();
+    }
+}
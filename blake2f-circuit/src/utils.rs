@@ -1,9 +1,4 @@
-//use halo2_proofs::circuit::Value;
-
-pub enum Value<T> {
-    Assigned(T),
-    Unassigned,
-}
+use halo2_proofs::{arithmetic::FieldExt, circuit::Value};
 
 pub const MASK_EVEN_32: u32 = 0x55555555;
 
@@ -103,20 +98,21 @@ pub fn odd_bits<const LEN: usize, const HALF: usize>(bits: [bool; LEN]) -> [bool
     odd_bits
 }
 
-/// Given a vector of words as vec![(lo: u16, hi: u16)], returns their sum: u32, along
-/// with a carry bit.
-pub fn sum_with_carry(words: Vec<(Value<u16>, Value<u16>)>) -> (Value<u32>, Value<u64>) {
-    let words_lo: Value<Vec<u64>> = words.iter().map(|(lo, _)| lo.map(|lo| lo as u64)).collect();
-    let words_hi: Value<Vec<u64>> = words.iter().map(|(_, hi)| hi.map(|hi| hi as u64)).collect();
+/// Given a vector of 64-bit words, returns their sum modulo 2^64 along with
+/// the carry out of the top bit. The carry is at most `words.len() - 1`.
+pub fn sum_with_carry(words: Vec<Value<u64>>) -> (Value<u64>, Value<u64>) {
+    let words: Value<Vec<u64>> = words.into_iter().collect();
 
-    let sum: Value<u64> = {
-        let sum_lo: Value<u64> = words_lo.map(|vec| vec.iter().sum());
-        let sum_hi: Value<u64> = words_hi.map(|vec| vec.iter().sum());
-        sum_lo.zip(sum_hi).map(|(lo, hi)| lo + (1 << 16) * hi)
-    };
+    let sum: Value<u128> = words.map(|vec| vec.iter().map(|word| *word as u128).sum());
 
-    let carry = sum.map(|sum| sum >> 32);
-    let sum = sum.map(|sum| sum as u32);
+    let carry = sum.map(|sum| (sum >> 64) as u64);
+    let sum = sum.map(|sum| sum as u64);
 
     (sum, carry)
-}
\ No newline at end of file
+}
+
+/// Returns the random linear combination of `bytes` with randomness `r`, the
+/// first byte getting the highest power of `r`.
+pub fn rlc<F: FieldExt>(bytes: &[u8], r: F) -> F {
+    bytes.iter().fold(F::zero(), |acc, byte| acc * r + F::from(*byte as u64))
+}
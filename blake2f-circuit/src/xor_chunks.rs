@@ -1,161 +1,559 @@
+// XOR of 64-bit words through a spread table.
+//
+// The spread form of a chunk interleaves its bits with zeros, so adding the
+// spread forms of two chunks never carries from one bit position into the
+// next: bit 2i of the sum is a_i ^ b_i and bit 2i + 1 is a_i & b_i. Splitting
+// the sum into its even and odd bits, both looked up in the spread table,
+// proves the XOR with four lookups per chunk.
+//
+// The same works for three words: every pair of bits of the sum is then at
+// most 3, so it still never carries, its even bit is a_i ^ b_i ^ c_i and its
+// odd bit is the majority of a_i, b_i and c_i.
+use std::marker::PhantomData;
 
+use halo2_proofs::{
+    arithmetic::FieldExt,
+    circuit::{AssignedCell, Chip, Layouter, Region, Value},
+    plonk::{Advice, Column, ConstraintSystem, Error, Expression, Selector, TableColumn},
+    poly::Rotation,
+};
+
+use crate::utils::{even_bits, i2lebsp, lebs2ip, odd_bits, spread_bits};
+
+// Words are split into bytes, so that rotating a word by a whole number of
+// bytes only permutes its chunks
+pub const CHUNK_SIZE: usize = 8;
+pub const NUM_CHUNKS: usize = 64 / CHUNK_SIZE;
+
+/// Returns the spread form of an 8-bit chunk.
+pub fn spread(chunk: u8) -> u16 {
+    lebs2ip(&spread_bits::<8, 16>(i2lebsp::<8>(chunk.into()))) as u16
+}
+
+/// Splits a sum of spread chunks into its even and odd bits.
+pub fn split_spread(sum: u16) -> (u8, u8) {
+    let bits = i2lebsp::<16>(sum.into());
+    (
+        lebs2ip(&even_bits::<16, 8>(bits)) as u8,
+        lebs2ip(&odd_bits::<16, 8>(bits)) as u8,
+    )
+}
+
+fn chunks(word: u64) -> [u8; NUM_CHUNKS] {
+    word.to_le_bytes()
+}
+
+fn from_chunks(chunks: [u8; NUM_CHUNKS]) -> u64 {
+    u64::from_le_bytes(chunks)
+}
 
-// xor mod from halo2 gadgets
-pub mod xor {
-    use crate::util::Expr;
-    use halo2_proofs::{arithmetic::FieldExt, plonk::Expression};
+/// A table of every 8-bit chunk next to its spread form. Looking a chunk up
+/// also range-checks it to 8 bits.
+#[derive(Clone, Copy, Debug)]
+pub struct SpreadTableConfig {
+    pub dense: TableColumn,
+    pub spread: TableColumn,
+}
 
-    /// Returns an expression that represents the XOR of the given expression.
-    pub fn expr<F: FieldExt, E: Expr<F>>(a: E, b: E) -> Expression<F> {
-        a.expr() + b.expr() - 2.expr() * a.expr() * b.expr()
+impl SpreadTableConfig {
+    pub fn configure<F: FieldExt>(meta: &mut ConstraintSystem<F>) -> Self {
+        Self {
+            dense: meta.lookup_table_column(),
+            spread: meta.lookup_table_column(),
+        }
     }
 
-    /// Returns a value that represents the XOR of the given value.
-    pub fn value<F: FieldExt>(a: F, b: F) -> F {
-        a + b - F::from(2u64) * a * b
+    pub fn load<F: FieldExt>(&self, layouter: &mut impl Layouter<F>) -> Result<(), Error> {
+        layouter.assign_table(
+            || "spread table",
+            |mut table| {
+                for chunk in 0..=u8::MAX {
+                    let offset = chunk as usize;
+                    table.assign_cell(
+                        || "dense",
+                        self.dense,
+                        offset,
+                        || Value::known(F::from(chunk as u64)),
+                    )?;
+                    table.assign_cell(
+                        || "spread",
+                        self.spread,
+                        offset,
+                        || Value::known(F::from(spread(chunk) as u64)),
+                    )?;
+                }
+                Ok(())
+            },
+        )
     }
 }
 
-// compression function written by chatgpt, todo implement the spread and run test
-use halo2_proofs::{
-    arithmetic::FieldExt,
-    circuit::{Layouter, SimpleFloorPlanner},
-    plonk::{Advice, Any, Column, ConstraintSystem, Error, Expression, Fixed, Selector},
-    poly::Rotation,
-    dev::{MockProver, VerifyFailure},
-};
-use halo2_proofs::plonk::Circuit;
+/// A word assigned on a row, along with the cells of its dense chunks.
+#[derive(Clone, Debug)]
+pub struct DecomposedWord<F: FieldExt> {
+    pub word: AssignedCell<F, F>,
+    pub dense: [AssignedCell<F, F>; NUM_CHUNKS],
+}
 
-use halo2_proofs::pasta::Fp;
-use rand::rngs::OsRng;
+/// Every row holds one 64-bit word, decomposed into 8-bit chunks that are
+/// looked up in the spread table. An XOR takes four rows:
+///
+/// | word | dense[0..8]       | spread[0..8]        |        |
+/// |------|-------------------|---------------------|--------|
+/// | lhs  | chunks of lhs     | spread(lhs chunks)  | q_xor  |
+/// | rhs  | chunks of rhs     | spread(rhs chunks)  |        |
+/// | and  | chunks of lhs&rhs | spread(odd bits)    |        |
+/// | out  | chunks of lhs^rhs | spread(even bits)   |        |
+///
+/// A three-input XOR takes five rows the same way, with its three operands
+/// on the first three rows under `q_xor3`.
+#[derive(Clone, Debug)]
+pub struct XorConfig {
+    pub word: Column<Advice>,
+    pub dense: [Column<Advice>; NUM_CHUNKS],
+    pub spread: [Column<Advice>; NUM_CHUNKS],
+    pub table: SpreadTableConfig,
+    q_decompose: Selector,
+    q_xor: Selector,
+    q_xor3: Selector,
+}
 
-// chunk size for xor and rotation are different
-// for xor 
-const CHUNK_SIZE: usize = 16;
-const NUM_CHUNKS: usize = 64 / CHUNK_SIZE;
+impl XorConfig {
+    /// Assigns `value` on `offset` as a word decomposed into spread chunks.
+    pub fn assign_word<F: FieldExt>(
+        &self,
+        region: &mut Region<'_, F>,
+        offset: usize,
+        value: Value<u64>,
+    ) -> Result<DecomposedWord<F>, Error> {
+        let dense = value.map(|value| chunks(value).map(u64::from));
+        self.assign_decomposition(region, offset, value.map(F::from), dense)
+    }
+
+    /// Assigns `word` on `offset` decomposed into the chunks `dense`, which
+    /// the lookups reject unless they are all bytes, so that only a word
+    /// below 2^64 has a decomposition. A chunk that is not a byte takes
+    /// itself as its spread form.
+    pub fn assign_decomposition<F: FieldExt>(
+        &self,
+        region: &mut Region<'_, F>,
+        offset: usize,
+        word: Value<F>,
+        dense: Value<[u64; NUM_CHUNKS]>,
+    ) -> Result<DecomposedWord<F>, Error> {
+        self.q_decompose.enable(region, offset)?;
+        let mut cells = Vec::with_capacity(NUM_CHUNKS);
+        for idx in 0..NUM_CHUNKS {
+            let chunk = dense.map(|dense| dense[idx]);
+            let spread_chunk = chunk.map(|chunk| u8::try_from(chunk).map_or(chunk, |chunk| spread(chunk) as u64));
+            cells.push(region.assign_advice(
+                || format!("dense {}", idx),
+                self.dense[idx],
+                offset,
+                || chunk.map(F::from),
+            )?);
+            region.assign_advice(
+                || format!("spread {}", idx),
+                self.spread[idx],
+                offset,
+                || spread_chunk.map(F::from),
+            )?;
+        }
+        let word = region.assign_advice(|| "word", self.word, offset, || word)?;
+        Ok(DecomposedWord {
+            word,
+            dense: cells.try_into().unwrap(),
+        })
+    }
+
+    /// Assigns the XOR of the words on rows `offset` and `offset + 1`, which
+    /// the caller has already assigned with `assign_word`. The result lands
+    /// on `offset + 3`.
+    pub fn assign_xor<F: FieldExt>(
+        &self,
+        region: &mut Region<'_, F>,
+        offset: usize,
+        lhs: Value<u64>,
+        rhs: Value<u64>,
+    ) -> Result<DecomposedWord<F>, Error> {
+        self.q_xor.enable(region, offset)?;
+
+        let (even, odd) = lhs
+            .zip(rhs)
+            .map(|(lhs, rhs)| {
+                let (lhs, rhs) = (chunks(lhs), chunks(rhs));
+                let mut even = [0; NUM_CHUNKS];
+                let mut odd = [0; NUM_CHUNKS];
+                for idx in 0..NUM_CHUNKS {
+                    (even[idx], odd[idx]) = split_spread(spread(lhs[idx]) + spread(rhs[idx]));
+                }
+                (from_chunks(even), from_chunks(odd))
+            })
+            .unzip();
 
-struct XorCircuit<F: FieldExt> {
-    a: Option<[u8; NUM_CHUNKS]>,
-    b: Option<[u8; NUM_CHUNKS]>,
-    _marker: std::marker::PhantomData<F>,
+        self.assign_word(region, offset + 2, odd)?;
+        self.assign_word(region, offset + 3, even)
+    }
+
+    /// Assigns the XOR of the words on rows `offset` to `offset + 2`, which
+    /// the caller has already assigned with `assign_word`. The result lands
+    /// on `offset + 4`.
+    pub fn assign_xor3<F: FieldExt>(
+        &self,
+        region: &mut Region<'_, F>,
+        offset: usize,
+        words: [Value<u64>; 3],
+    ) -> Result<DecomposedWord<F>, Error> {
+        self.q_xor3.enable(region, offset)?;
+
+        let [a, b, c] = words;
+        let (even, odd) = a
+            .zip(b)
+            .zip(c)
+            .map(|((a, b), c)| {
+                let (a, b, c) = (chunks(a), chunks(b), chunks(c));
+                let mut even = [0; NUM_CHUNKS];
+                let mut odd = [0; NUM_CHUNKS];
+                for idx in 0..NUM_CHUNKS {
+                    let sum = spread(a[idx]) + spread(b[idx]) + spread(c[idx]);
+                    (even[idx], odd[idx]) = split_spread(sum);
+                }
+                (from_chunks(even), from_chunks(odd))
+            })
+            .unzip();
+
+        self.assign_word(region, offset + 3, odd)?;
+        self.assign_word(region, offset + 4, even)
+    }
 }
-#[derive(Clone)]
-pub struct XorConfig {
-    a: Column<Advice>,
-    b: Column<Advice>,
-    out: Column<Advice>,
-    table: Column<Fixed>,
+
+#[derive(Clone, Debug)]
+pub struct XorChip<F: FieldExt> {
+    config: XorConfig,
+    _marker: PhantomData<F>,
 }
 
-impl<F: FieldExt> Circuit<F> for XorCircuit<F> {
+impl<F: FieldExt> Chip<F> for XorChip<F> {
     type Config = XorConfig;
+    type Loaded = ();
+
+    fn config(&self) -> &Self::Config {
+        &self.config
+    }
 
-    fn configure(meta: &mut ConstraintSystem<F>) -> XorConfig {
-        let a = meta.advice_column();
-        let b = meta.advice_column();
-        let out = meta.advice_column();
-        let table = meta.fixed_column();
+    fn loaded(&self) -> &Self::Loaded {
+        &()
+    }
+}
+
+impl<F: FieldExt> XorChip<F> {
+    pub fn construct(config: XorConfig) -> Self {
+        Self {
+            config,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Configures the XOR gate over `word`, which must have equality enabled,
+    /// and freshly allocated chunk columns. The dense chunk columns have
+    /// equality enabled too, so words can be copied chunk by chunk.
+    pub fn configure(
+        meta: &mut ConstraintSystem<F>,
+        word: Column<Advice>,
+        table: SpreadTableConfig,
+    ) -> XorConfig {
+        let dense = [(); NUM_CHUNKS].map(|_| meta.advice_column());
+        let spread = [(); NUM_CHUNKS].map(|_| meta.advice_column());
+        for column in dense.iter() {
+            meta.enable_equality(*column);
+        }
+        let q_decompose = meta.complex_selector();
+        let q_xor = meta.selector();
+        let q_xor3 = meta.selector();
 
-        meta.lookup(|meta| {
-            let a = meta.query_advice(a, Rotation::cur());
-            let b = meta.query_advice(b, Rotation::cur());
-            let out = meta.query_advice(out, Rotation::cur());
-            let t = meta.query_fixed(table, Rotation::cur());
+        for idx in 0..NUM_CHUNKS {
+            meta.lookup("spread chunk", |meta| {
+                let q_decompose = meta.query_selector(q_decompose);
+                let dense = meta.query_advice(dense[idx], Rotation::cur());
+                let spread = meta.query_advice(spread[idx], Rotation::cur());
+                vec![
+                    (q_decompose.clone() * dense, table.dense),
+                    (q_decompose * spread, table.spread),
+                ]
+            });
+        }
 
-            vec![(a + b - t, out)]
+        meta.create_gate("decompose", |meta| {
+            let q_decompose = meta.query_selector(q_decompose);
+            let word = meta.query_advice(word, Rotation::cur());
+            let composed = dense.iter().rev().fold(Expression::Constant(F::zero()), |acc, chunk| {
+                acc * Expression::Constant(F::from(1 << CHUNK_SIZE))
+                    + meta.query_advice(*chunk, Rotation::cur())
+            });
+            vec![q_decompose * (composed - word)]
         });
 
-        XorConfig { a, b, out, table }
+        meta.create_gate("xor", |meta| {
+            let q_xor = meta.query_selector(q_xor);
+            (0..NUM_CHUNKS)
+                .map(|idx| {
+                    let lhs = meta.query_advice(spread[idx], Rotation::cur());
+                    let rhs = meta.query_advice(spread[idx], Rotation::next());
+                    let odd = meta.query_advice(spread[idx], Rotation(2));
+                    let even = meta.query_advice(spread[idx], Rotation(3));
+                    q_xor.clone()
+                        * (lhs + rhs - even - odd * Expression::Constant(F::from(2)))
+                })
+                .collect::<Vec<_>>()
+        });
+
+        meta.create_gate("xor3", |meta| {
+            let q_xor3 = meta.query_selector(q_xor3);
+            (0..NUM_CHUNKS)
+                .map(|idx| {
+                    let a = meta.query_advice(spread[idx], Rotation::cur());
+                    let b = meta.query_advice(spread[idx], Rotation::next());
+                    let c = meta.query_advice(spread[idx], Rotation(2));
+                    let odd = meta.query_advice(spread[idx], Rotation(3));
+                    let even = meta.query_advice(spread[idx], Rotation(4));
+                    q_xor3.clone()
+                        * (a + b + c - even - odd * Expression::Constant(F::from(2)))
+                })
+                .collect::<Vec<_>>()
+        });
+
+        XorConfig {
+            word,
+            dense,
+            spread,
+            table,
+            q_decompose,
+            q_xor,
+            q_xor3,
+        }
     }
 
-    fn synthesize(&self, config: XorConfig, mut layouter: impl Layouter<F>) -> Result<(), Error> {
-        // Fill the fixed table with XOR values for smaller chunks
-        layouter.assign_table(
-            || "xor_table",
-            |mut table| {
-                for i in 0..(1 << CHUNK_SIZE) {
-                    for j in 0..(1 << CHUNK_SIZE) {
-                        // todo update this xor function after using spread and interleaving of bits
-                        let xor_value = i ^ j;
-                        table.assign_cell(|| format!("table[{}][{}]", i, j), config.table, (i << CHUNK_SIZE) + j, || Ok(F::from_u64(xor_value as u64)))?;
-                    }
+    pub fn load(&self, layouter: &mut impl Layouter<F>) -> Result<(), Error> {
+        self.config.table.load(layouter)
+    }
+
+    /// Returns a cell constrained to the XOR of the words in `lhs` and `rhs`.
+    pub fn xor(
+        &self,
+        layouter: &mut impl Layouter<F>,
+        lhs: &AssignedCell<F, F>,
+        rhs: &AssignedCell<F, F>,
+    ) -> Result<AssignedCell<F, F>, Error> {
+        let config = &self.config;
+        layouter.assign_region(
+            || "xor",
+            |mut region| {
+                let values = [lhs, rhs].map(|cell| cell.value().map(|v| v.get_lower_128() as u64));
+                for (offset, (cell, value)) in [lhs, rhs].iter().zip(values).enumerate() {
+                    let copy = config.assign_word(&mut region, offset, value)?;
+                    region.constrain_equal(cell.cell(), copy.word.cell())?;
                 }
-                Ok(())
+                Ok(config.assign_xor(&mut region, 0, values[0], values[1])?.word)
             },
-        )?;
+        )
+    }
 
-        // Perform the XOR operation using lookup tables
+    /// Returns a cell constrained to the XOR of the three words in `words`.
+    pub fn xor3(
+        &self,
+        layouter: &mut impl Layouter<F>,
+        words: [&AssignedCell<F, F>; 3],
+    ) -> Result<AssignedCell<F, F>, Error> {
+        let config = &self.config;
         layouter.assign_region(
-            || "Xor",
+            || "xor3",
             |mut region| {
-                for i in 0..NUM_CHUNKS {
-                    let a_chunk = self.a.map(|a| a[i] as u64);
-                    let b_chunk = self.b.map(|b| b[i] as u64);
-                    let out_chunk = a_chunk.zip(b_chunk).map(|(a, b)| a ^ b);
-
-                    let a_cell = region.assign_advice(|| format!("a_chunk_{}", i), config.a, i, || a_chunk.ok_or(Error::SynthesisError))?;
-                    let b_cell = region.assign_advice(|| format!("b_chunk_{}", i), config.b, i, || b_chunk.ok_or(Error::SynthesisError))?;
-                    let out_cell = region.assign_advice(|| format!("out_chunk_{}", i), config.out, i, || out_chunk.ok_or(Error::SynthesisError))?;
-
-                    region.lookup_table(config.table, || {
-                        a_chunk.zip(b_chunk).ok_or(Error::SynthesisError)
-                    }, |table| {
-                        vec![
-                            (a_cell.into(), a_chunk),
-                            (b_cell.into(), b_chunk),
-                            (out_cell.into(), out_chunk),
-                            (table, None),
-                        ]
-                    })?;
+                let values = words.map(|cell| cell.value().map(|v| v.get_lower_128() as u64));
+                for (offset, (cell, value)) in words.iter().zip(values).enumerate() {
+                    let copy = config.assign_word(&mut region, offset, value)?;
+                    region.constrain_equal(cell.cell(), copy.word.cell())?;
                 }
+                Ok(config.assign_xor3(&mut region, 0, values)?.word)
+            },
+        )
+    }
+}
 
-                Ok(())
-            }
-        );
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use halo2_proofs::{
+        circuit::SimpleFloorPlanner,
+        dev::{MockProver, VerifyFailure},
+        halo2curves::bn256::Fr,
+        plonk::Circuit,
+    };
+
+    // XORs two or three words and checks the result
+    struct XorCircuit {
+        operands: Vec<u64>,
+        expected: u64,
     }
 
-    type FloorPlanner;
+    impl Circuit<Fr> for XorCircuit {
+        type Config = XorConfig;
+        type FloorPlanner = SimpleFloorPlanner;
+
+        fn without_witnesses(&self) -> Self {
+            Self {
+                operands: self.operands.clone(),
+                expected: self.expected,
+            }
+        }
+
+        fn configure(meta: &mut ConstraintSystem<Fr>) -> Self::Config {
+            let word = meta.advice_column();
+            meta.enable_equality(word);
+            let constants = meta.fixed_column();
+            meta.enable_constant(constants);
+            let table = SpreadTableConfig::configure(meta);
+            XorChip::configure(meta, word, table)
+        }
+
+        fn synthesize(&self, config: Self::Config, mut layouter: impl Layouter<Fr>) -> Result<(), Error> {
+            let chip = XorChip::construct(config.clone());
+            chip.load(&mut layouter)?;
 
-    fn without_witnesses(&self) -> Self {
-        todo!()
+            let words = layouter.assign_region(
+                || "inputs",
+                |mut region| {
+                    self.operands
+                        .iter()
+                        .enumerate()
+                        .map(|(offset, word)| {
+                            Ok(config.assign_word(&mut region, offset, Value::known(*word))?.word)
+                        })
+                        .collect::<Result<Vec<_>, Error>>()
+                },
+            )?;
+            let out = match words.as_slice() {
+                [a, b] => chip.xor(&mut layouter, a, b)?,
+                [a, b, c] => chip.xor3(&mut layouter, [a, b, c])?,
+                _ => panic!("can only xor two or three words"),
+            };
+
+            layouter.assign_region(
+                || "expected",
+                |mut region| region.constrain_constant(out.cell(), Fr::from(self.expected)),
+            )
+        }
     }
 
-    
-}
+    // Witnesses a word next to the given dense chunks, each with its spread
+    // form if it is a byte
+    struct DecomposeCircuit {
+        word: Fr,
+        dense: [u64; NUM_CHUNKS],
+    }
 
+    impl Circuit<Fr> for DecomposeCircuit {
+        type Config = XorConfig;
+        type FloorPlanner = SimpleFloorPlanner;
 
+        fn without_witnesses(&self) -> Self {
+            Self {
+                word: self.word,
+                dense: self.dense,
+            }
+        }
 
-// Include the XorCircuit definition and other necessary imports here
+        fn configure(meta: &mut ConstraintSystem<Fr>) -> Self::Config {
+            XorCircuit::configure(meta)
+        }
 
-fn test_xor_circuit(a: u64, b: u64) {
-    let mut rng = OsRng;
+        fn synthesize(&self, config: Self::Config, mut layouter: impl Layouter<Fr>) -> Result<(), Error> {
+            config.table.load(&mut layouter)?;
 
-    let a_chunks = u64_to_chunks(a, CHUNK_SIZE);
-    let b_chunks = u64_to_chunks(b, CHUNK_SIZE);
+            layouter.assign_region(
+                || "word",
+                |mut region| {
+                    let (word, dense) = (Value::known(self.word), Value::known(self.dense));
+                    config.assign_decomposition(&mut region, 0, word, dense)?;
+                    Ok(())
+                },
+            )
+        }
+    }
 
-    let circuit = XorCircuit::<Fp> {
-        a: Some(a_chunks),
-        b: Some(b_chunks),
-        _marker: std::marker::PhantomData,
-    };
+    #[test]
+    fn test_word_decomposition() {
+        let word = 0x0123456789abcdef;
+        let circuit = DecomposeCircuit {
+            word: Fr::from(word),
+            dense: chunks(word).map(u64::from),
+        };
+        let prover = MockProver::run(10, &circuit, vec![]).unwrap();
+        assert_eq!(prover.verify(), Ok(()));
+    }
 
-    let prover = MockProver::run(1, &circuit, vec![]).unwrap();
+    #[test]
+    fn test_word_out_of_range() {
+        // 2^64 is composed of the chunks of a word, but its top chunk is 256
+        let two_pow_64 = Fr::from(u64::MAX) + Fr::from(1);
+        let circuit = DecomposeCircuit {
+            word: two_pow_64,
+            dense: [0, 0, 0, 0, 0, 0, 0, 256],
+        };
+        let prover = MockProver::run(10, &circuit, vec![]).unwrap();
+        let failures = prover.verify().unwrap_err();
+        assert!(failures.iter().all(|failure| matches!(failure, VerifyFailure::Lookup { .. })));
 
-    let result = prover.verify(&[]);
-    assert!(result.is_ok());
-}
+        // and it is not composed of any chunks that are all bytes
+        let circuit = DecomposeCircuit {
+            word: two_pow_64,
+            dense: [0; NUM_CHUNKS],
+        };
+        let prover = MockProver::run(10, &circuit, vec![]).unwrap();
+        assert!(prover.verify().is_err());
+    }
 
-fn u64_to_chunks(value: u64, chunk_size: usize) -> [u8; NUM_CHUNKS] {
-    let mut chunks = [0u8; NUM_CHUNKS];
-    for i in 0..NUM_CHUNKS {
-        let shift = i * chunk_size;
-        chunks[i] = ((value >> shift) & ((1 << chunk_size) - 1)) as u8;
+    #[test]
+    fn test_xor_chip() {
+        let (a, b) = (0x0123456789ABCDEF, 0x89ABCDEF01234567);
+        let circuit = XorCircuit {
+            operands: vec![a, b],
+            expected: a ^ b,
+        };
+        let prover = MockProver::run(10, &circuit, vec![]).unwrap();
+        assert_eq!(prover.verify(), Ok(()));
     }
-    chunks
-}
 
-fn main() {
-    let a: u64 = 0x0123456789ABCDEF;
-    let b: u64 = 0x89ABCDEF01234567;
-    test_xor_circuit(a, b);
+    #[test]
+    fn test_xor_chip_wrong_output() {
+        let (a, b) = (0x0123456789ABCDEF, 0x89ABCDEF01234567);
+        let circuit = XorCircuit {
+            operands: vec![a, b],
+            expected: a | b,
+        };
+        let prover = MockProver::run(10, &circuit, vec![]).unwrap();
+        assert!(prover.verify().is_err());
+    }
+
+    #[test]
+    fn test_xor3_chip() {
+        let (a, b, c) = (0x0123456789ABCDEF, 0x89ABCDEF01234567, u64::MAX);
+        let circuit = XorCircuit {
+            operands: vec![a, b, c],
+            expected: a ^ b ^ c,
+        };
+        let prover = MockProver::run(10, &circuit, vec![]).unwrap();
+        assert_eq!(prover.verify(), Ok(()));
+    }
+
+    #[test]
+    fn test_xor3_chip_wrong_output() {
+        // The majority of the three words, which the odd bits hold
+        let (a, b, c) = (0x0123456789ABCDEF, 0x89ABCDEF01234567, u64::MAX);
+        let circuit = XorCircuit {
+            operands: vec![a, b, c],
+            expected: (a & b) | (b & c) | (a & c),
+        };
+        let prover = MockProver::run(10, &circuit, vec![]).unwrap();
+        assert!(prover.verify().is_err());
+    }
 }